@@ -0,0 +1,201 @@
+//! Checking albums for missing and extra tracks, using the track and disc totals in tags where
+//! files were probed, and the numbering of the tracks otherwise
+
+use core::fmt;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{order, Audiophile, Order, OrderedBy};
+
+/// A run of consecutive tracks or discs missing from an album, from `first` to `last`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gap<T> {
+    pub first: T,
+    pub last: T,
+}
+
+impl<T: fmt::Display + PartialEq> fmt::Display for Gap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.first == self.last {
+            write!(f, "{}", self.first)
+        } else {
+            write!(f, "{} to {}", self.first, self.last)
+        }
+    }
+}
+
+/// The completeness verdict of an album
+#[derive(Debug, Default)]
+pub struct Completeness {
+    /// tracks missing from a disc, below its total or between tracks that are there
+    pub missing: Vec<Gap<Order>>,
+    /// tracks numbered beyond the total of their disc
+    pub extra: Vec<Order>,
+    /// discs with no tracks at all, below the disc total or between discs that are there
+    pub missing_discs: Vec<Gap<u64>>,
+}
+
+impl Completeness {
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.missing_discs.is_empty()
+    }
+}
+
+impl fmt::Display for Completeness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list<T: ToString>(items: &[T]) -> String {
+            items
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+
+        let mut parts = vec![];
+
+        if !self.missing.is_empty() {
+            parts.push(format!("missing tracks {}", list(&self.missing)));
+        }
+
+        if !self.extra.is_empty() {
+            parts.push(format!("tracks beyond the total {}", list(&self.extra)));
+        }
+
+        if !self.missing_discs.is_empty() {
+            parts.push(format!("missing discs {}", list(&self.missing_discs)));
+        }
+
+        f.write_str(&parts.join("; "))
+    }
+}
+
+/// The runs of numbers from 1 to `last` that are not in `present`
+fn gaps(present: &BTreeSet<u64>, last: u64) -> Vec<Gap<u64>> {
+    let mut gaps = vec![];
+    let mut next = 1;
+
+    for &n in present
+        .iter()
+        .filter(|&&n| n != 0)
+        .take_while(|&&n| n <= last)
+    {
+        if n > next {
+            gaps.push(Gap {
+                first: next,
+                last: n - 1,
+            });
+        }
+
+        next = n + 1;
+    }
+
+    if next <= last {
+        gaps.push(Gap { first: next, last });
+    }
+
+    gaps
+}
+
+/// Checks the numbering of `tracks` against itself and any totals in their tags, tracks pinned
+/// by the album config or sorted without a number are left out
+///
+/// Numbers and totals far above the number of tracks, like a catalog number in a track tag, are
+/// left out as well, see [`order::too_many_tracks`]
+pub fn check(tracks: &[Audiophile]) -> Completeness {
+    let files = tracks.len();
+
+    let mut discs: BTreeMap<Option<u64>, (BTreeSet<u64>, Option<u64>)> = BTreeMap::new();
+    let mut disc_total = None;
+
+    for af in tracks {
+        if af.ordered_by == OrderedBy::Config || af.after.is_some() {
+            continue;
+        }
+
+        if order::too_many_tracks(af.order.track, files)
+            || af
+                .order
+                .disc
+                .is_some_and(|d| order::too_many_discs(d, files))
+        {
+            continue;
+        }
+
+        let (numbers, total) = discs.entry(af.order.disc).or_default();
+        numbers.insert(af.order.track);
+
+        let track_total = order::track_total(af.info.tags());
+        let album_discs = order::disc_total(af.info.tags());
+
+        // files of one disc should agree, but the largest total is the safest to check against
+        *total = (*total).max(track_total.filter(|&t| !order::too_many_tracks(t, files)));
+        disc_total = disc_total.max(album_discs.filter(|&t| !order::too_many_discs(t, files)));
+    }
+
+    let mut res = Completeness::default();
+
+    for (&disc, (numbers, total)) in &discs {
+        let last = numbers.last().copied().unwrap_or_default();
+        let order = |track| Order {
+            disc,
+            track,
+            sub: None,
+        };
+
+        res.missing.extend(
+            gaps(numbers, total.unwrap_or(last))
+                .into_iter()
+                .map(|gap| Gap {
+                    first: order(gap.first),
+                    last: order(gap.last),
+                }),
+        );
+
+        if let Some(after) = total.and_then(|total| total.checked_add(1)) {
+            res.extra
+                .extend(numbers.range(after..).map(|&track| order(track)));
+        }
+    }
+
+    let present: BTreeSet<u64> = discs.keys().flatten().copied().collect();
+
+    if let Some(&last) = present.last() {
+        res.missing_discs = gaps(&present, disc_total.unwrap_or(last).max(last));
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::{gaps, Gap};
+
+    fn gap(first: u64, last: u64) -> Gap<u64> {
+        Gap { first, last }
+    }
+
+    #[test]
+    fn gaps_between_and_after() {
+        let present = BTreeSet::from([1, 2, 5, 9]);
+
+        assert_eq!(gaps(&present, 9), [gap(3, 4), gap(6, 8)]);
+        assert_eq!(gaps(&present, 11), [gap(3, 4), gap(6, 8), gap(10, 11)]);
+
+        // numbers beyond the total are extra, not the end of a gap
+        assert_eq!(gaps(&present, 4), [gap(3, 4)]);
+    }
+
+    #[test]
+    fn gaps_ignore_track_zero() {
+        assert_eq!(gaps(&BTreeSet::from([0, 2]), 2), [gap(1, 1)]);
+        assert!(gaps(&BTreeSet::from([0]), 0).is_empty());
+        assert_eq!(gaps(&BTreeSet::new(), 3), [gap(1, 3)]);
+    }
+
+    #[test]
+    fn gaps_display_as_ranges() {
+        assert_eq!(gap(3, 3).to_string(), "3");
+        assert_eq!(gap(3, 5).to_string(), "3 to 5");
+    }
+}
@@ -0,0 +1,114 @@
+//! Per album overrides, read from a `.playlister.toml` in the album directory, and
+//! `.playlisterignore` files
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::TrackZero;
+
+pub const FILENAME: &str = ".playlister.toml";
+pub const IGNORE_FILENAME: &str = ".playlisterignore";
+
+/// An error for a config or ignore file at `path` that could not be parsed
+fn invalid_data(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("`{}`: {e}", path.display()),
+    )
+}
+
+/// Overrides for albums with broken tags or unfixable filenames, all paths are relative to the
+/// album directory
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlbumConfig {
+    /// title of the playlist, for formats that have one
+    pub title: Option<String>,
+    /// filename to write the playlist to, used when none is given on the command line
+    pub outfile: Option<PathBuf>,
+    /// files to leave out of the playlist
+    pub exclude: Vec<PathBuf>,
+    /// files in playlist order, these are placed before any unlisted files
+    pub order: Vec<PathBuf>,
+    /// extra extensions to treat as audio files
+    pub ext: Vec<String>,
+    /// extensions to no longer treat as audio files
+    pub no_ext: Vec<String>,
+    /// where to read the order of a file from, each source is tried in turn
+    pub order_by: Vec<String>,
+    /// regexes for the `regex` order source, tried in turn
+    pub pattern: Vec<String>,
+    /// track titles in playlist order, matched against the title tag or filename of each file
+    pub tracklist: Vec<String>,
+    /// where to put a hidden pregap track numbered 0
+    pub track_zero: Option<TrackZero>,
+}
+
+impl AlbumConfig {
+    /// Reads the config of the album in `dir`, an album without one gets the default config
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(FILENAME);
+
+        match fs::read_to_string(&path) {
+            Ok(s) => toml::from_str(&s).map_err(|e| invalid_data(&path, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn excludes(&self, name: &Path) -> bool {
+        self.exclude.iter().any(|e| e == name)
+    }
+
+    /// Position of `name` in the pinned order, if it is listed
+    pub fn position(&self, name: &Path) -> Option<usize> {
+        self.order.iter().position(|o| o == name)
+    }
+}
+
+/// Gitignore style patterns from the `.playlisterignore` files of a directory and its parents,
+/// with the innermost directory last
+#[derive(Clone, Default)]
+pub struct Ignores(Vec<Gitignore>);
+
+impl Ignores {
+    /// Adds the ignore file of `dir`, if it has one, its patterns are relative to `dir`
+    pub fn with_dir(&self, dir: &Path) -> io::Result<Self> {
+        let path = dir.join(IGNORE_FILENAME);
+
+        let s = match fs::read_to_string(&path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(self.clone()),
+            Err(e) => return Err(e),
+        };
+
+        let invalid = |e| invalid_data(&path, e);
+
+        let mut builder = GitignoreBuilder::new(dir);
+
+        for line in s.lines() {
+            builder
+                .add_line(Some(path.clone()), line)
+                .map_err(invalid)?;
+        }
+
+        let mut ignores = self.clone();
+        ignores.0.push(builder.build().map_err(invalid)?);
+
+        Ok(ignores)
+    }
+
+    /// Whether `path` is ignored, a whitelist (`!`) pattern in an inner directory overrides an
+    /// ignore in an outer one
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.0
+            .iter()
+            .rev()
+            .map(|gi| gi.matched(path, is_dir))
+            .find(|m| !m.is_none())
+            .is_some_and(|m| m.is_ignore())
+    }
+}
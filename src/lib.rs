@@ -0,0 +1,1129 @@
+//! A simple CLI to generate a playlist from a cdrip'ed album, as a library so custom builds can
+//! add order sources of their own
+//!
+//! A file is put in its place by the first [`OrderSource`] that knows it, in the order given to
+//! `--order-by`. A binary adding a source implements the trait and hands it to [`cli`], which then
+//! takes its name on the command line and in album configs like the name of a builtin source
+//!
+//! ```no_run
+//! use std::{io, process::ExitCode};
+//!
+//! use playlister::{Candidate, CustomSource, Order, OrderSource, Position};
+//!
+//! /// Orders files named like `track7.flac`
+//! struct TrackWord;
+//!
+//! impl OrderSource for TrackWord {
+//!     fn name(&self) -> &'static str {
+//!         "track-word"
+//!     }
+//!
+//!     fn position(&mut self, file: &mut Candidate) -> io::Result<Option<Position>> {
+//!         let fname = file.file_name();
+//!         let track = fname
+//!             .strip_prefix("track")
+//!             .and_then(|rest| rest.split('.').next()?.parse().ok());
+//!
+//!         Ok(track.map(|track| {
+//!             Position::Numbered(Order {
+//!                 disc: None,
+//!                 track,
+//!                 sub: None,
+//!             })
+//!         }))
+//!     }
+//! }
+//!
+//! fn main() -> ExitCode {
+//!     playlister::cli(&[CustomSource {
+//!         name: "track-word",
+//!         make: || Box::new(TrackWord),
+//!     }])
+//! }
+//! ```
+//!
+//! Sources can also be built and tried on their own with [`source::chain`]
+//!
+//! ```
+//! use std::{io, path::Path};
+//!
+//! use playlister::{source, Candidate, Position};
+//!
+//! let mut sources = source::chain(["filename", "natural"], &[], &[], |_| None)?;
+//! let mut file = Candidate::new(".".as_ref(), Path::new("07 song.flac"), 1, false);
+//!
+//! assert!(matches!(
+//!     sources[0].position(&mut file)?,
+//!     Some(Position::Numbered(order)) if order.track == 7
+//! ));
+//! # Ok::<(), io::Error>(())
+//! ```
+
+#![warn(clippy::pedantic)]
+
+use core::fmt;
+use std::{
+    collections::HashSet,
+    error::Error,
+    ffi::OsStr,
+    fs, io,
+    path::{Component, Path, PathBuf},
+    process::ExitCode,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use clap::{builder::PossibleValuesParser, CommandFactory, FromArgMatches};
+
+pub mod order;
+pub mod source;
+pub mod tags;
+
+mod check;
+mod config;
+mod library;
+mod playlist;
+
+pub use order::Order;
+pub use source::{Candidate, OrderSource, Position};
+
+use config::{AlbumConfig, Ignores};
+use tags::TrackInfo;
+
+/// An order source added by a custom build of playlister, see [`cli`]
+#[derive(Clone, Copy)]
+pub struct CustomSource {
+    /// the name to give `--order-by` or `order_by` in an album config
+    pub name: &'static str,
+    /// builds the source, once for every album it is used in
+    pub make: fn() -> Box<dyn OrderSource>,
+}
+
+/// Number of warnings written so far, so callers can tell if an operation warned
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes a warning to stderr, counting it in [`WARNINGS`]
+fn write_warn(msg: impl fmt::Display) {
+    use io::Write;
+    WARNINGS.fetch_add(1, Ordering::Relaxed);
+    let _ignore = writeln!(io::stderr().lock(), "\x1b[93mWARN:\x1b[0m {msg}");
+}
+
+/// The default audio extensions, all lowercase
+const AUDIO_EXT: phf::Set<&'static str> = phf::phf_set! {
+    // trash
+    "mp3",
+
+    // open codecs/containers
+    "flac",
+    "opus",
+    "ape",
+    "ogg",
+    "spx",
+    "mka",
+    "webm",
+    "wv",
+    "tta",
+    "mpc",
+
+    // apple stuff
+    "aac",
+    "alac",
+    "m4a",
+    "m4b",
+    "caf",
+    "aiff",
+    "aif",
+
+    // windows stuff
+    "wma",
+    "wav",
+
+    // dsd
+    "dsf",
+    "dff",
+};
+
+/// The set of extensions treated as audio files, matched case insensitively
+#[derive(Clone)]
+struct Extensions(HashSet<String>);
+
+impl Extensions {
+    /// The default extensions
+    fn new() -> Self {
+        Self(AUDIO_EXT.iter().map(|&ext| ext.to_owned()).collect())
+    }
+
+    /// These extensions with `add` added and then `remove` removed
+    fn with(mut self, add: &[String], remove: &[String]) -> Self {
+        // `.flac` and `FLAC` are accepted as well as `flac`
+        let normalize = |ext: &String| ext.trim_start_matches('.').to_ascii_lowercase();
+
+        self.0.extend(add.iter().map(normalize));
+
+        for ext in remove {
+            self.0.remove(&normalize(ext));
+        }
+
+        self
+    }
+
+    fn contains(&self, ext: &OsStr) -> bool {
+        // a non UTF-8 extension can never match
+        ext.to_str()
+            .is_some_and(|ext| self.0.contains(&ext.to_ascii_lowercase()))
+    }
+}
+
+#[derive(Debug)]
+struct Audiophile {
+    order: Order,
+    ordered_by: OrderedBy,
+    /// sort key of files placed after the numbered tracks, these have no track number
+    after: Option<u64>,
+    name: PathBuf,
+    info: TrackInfo,
+}
+
+/// Where the order of an [`Audiophile`] was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OrderedBy {
+    /// pinned by the album config
+    Config,
+    /// the builtin filename source, the only one cross checked against tags
+    Filename,
+    /// any other order source, by name
+    Source(&'static str),
+}
+
+impl OrderedBy {
+    /// Tells the builtin filename source apart from a custom source that happens to share its name
+    fn of(source: &dyn OrderSource) -> Self {
+        if source::is_filename(source) {
+            Self::Filename
+        } else {
+            Self::Source(source.name())
+        }
+    }
+
+    /// The name written to the json report and the progress output
+    fn name(self) -> &'static str {
+        match self {
+            Self::Config => "config",
+            Self::Filename => "filename",
+            Self::Source(name) => name,
+        }
+    }
+}
+
+/// The order source that wins when a file's name and tags disagree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Trust {
+    /// keep the order from the filename
+    Filename,
+    /// use the track and disc tags
+    Tags,
+}
+
+/// Where a hidden pregap track, numbered 0, goes in the playlist
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum TrackZero {
+    /// before the first track of its disc, as it is on the disc
+    #[default]
+    First,
+    /// after the last track of its disc
+    Last,
+    /// leave it out of the playlist
+    Exclude,
+}
+
+/// Whether `file` is an audio file, by its content when `sniff` is set or else by its extension
+fn is_audio(file: &Path, exts: &Extensions, sniff: bool) -> io::Result<bool> {
+    if sniff {
+        tags::is_audio(file)
+    } else {
+        Ok(file.extension().is_some_and(|ext| exts.contains(ext)))
+    }
+}
+
+impl Audiophile {
+    /// Playlist position, files pinned by the album config come first and files without a number
+    /// last
+    fn rank(&self) -> (u8, Order, u64) {
+        match self.after {
+            _ if self.ordered_by == OrderedBy::Config => (0, self.order, 0),
+            None => (1, self.order, 0),
+            Some(key) => (2, self.order, key),
+        }
+    }
+
+    /// Probes a filename ordered file for metadata, taking its disc from tags if the filename had none
+    fn probe(&mut self, dir: &Path) -> io::Result<()> {
+        self.info = tags::probe(&dir.join(&self.name))?;
+
+        if self.order.disc.is_none() {
+            self.order.disc = order::disc_tag(self.info.tags());
+        }
+
+        Ok(())
+    }
+}
+
+/// The step of scanning a file failed at
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Stage {
+    /// listing a directory, or reading the type of an entry in it
+    List,
+    /// reading the start of a file to tell if it is audio
+    Sniff,
+    /// reading tags and stream info
+    Probe,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::List => "list",
+            Self::Sniff => "sniff",
+            Self::Probe => "probe",
+        })
+    }
+}
+
+/// A file or directory that could not be read while scanning an album, the rest of the album is
+/// still scanned
+#[derive(Debug)]
+struct Diagnostic {
+    path: PathBuf,
+    stage: Stage,
+    error: io::Error,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "could not {} `{}`: {}",
+            self.stage,
+            self.path.display(),
+            self.error
+        )
+    }
+}
+
+/// State shared while scanning an album directory and its disc subdirectories
+struct Collector<'a> {
+    dir: &'a Path,
+    config: &'a AlbumConfig,
+    ignores: Ignores,
+    exts: Extensions,
+    sniff: bool,
+    probe: bool,
+    /// which source wins when filenames and tags disagree, if they are compared at all
+    cross_check: Option<Trust>,
+    /// order sources tried in turn for every file not pinned by the album config
+    sources: Vec<Box<dyn OrderSource>>,
+    /// audio files waiting to be ordered, with the disc of their subdirectory
+    files: Vec<(PathBuf, Option<u64>)>,
+    res: Vec<Audiophile>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Collector<'_> {
+    /// Unwraps the result of a step of scanning `path`, recording a diagnostic if it failed
+    fn check<T>(&mut self, path: &Path, stage: Stage, res: io::Result<T>) -> Option<T> {
+        res.map_err(|error| {
+            self.diagnostics.push(Diagnostic {
+                path: path.to_owned(),
+                stage,
+                error,
+            });
+        })
+        .ok()
+    }
+
+    /// Adds a file to the album if it is an audio file, `name` is relative to the album directory
+    /// and `disc` is set when the file is in a disc subdirectory
+    fn file(&mut self, name: PathBuf, disc: Option<u64>) {
+        let path = self.dir.join(&name);
+
+        if self.config.excludes(&name) || self.ignores.is_ignored(&path, false) {
+            return;
+        }
+
+        // pinned files are taken as is, even if they could not otherwise be ordered
+        if let Some(pos) = self.config.position(&name) {
+            let info = if self.probe {
+                let Some(info) = self.check(&path, Stage::Probe, tags::probe(&path)) else {
+                    return;
+                };
+
+                info
+            } else {
+                TrackInfo::default()
+            };
+
+            self.res.push(Audiophile {
+                order: Order {
+                    disc: None,
+                    track: pos as u64 + 1,
+                    sub: None,
+                },
+                ordered_by: OrderedBy::Config,
+                after: None,
+                name,
+                info,
+            });
+            return;
+        }
+
+        let audio = is_audio(&path, &self.exts, self.sniff);
+
+        if self.check(&path, Stage::Sniff, audio) != Some(true) {
+            return; // this isn't an audio file, ignore
+        }
+
+        self.files.push((name, disc));
+    }
+
+    /// Orders every audio file found, once the number of files in the album is known
+    fn order_files(&mut self) {
+        let count = self.res.len() + self.files.len();
+
+        let fnames: Vec<_> = self
+            .files
+            .iter()
+            .map(|(name, _)| name.file_name().unwrap_or_default().to_string_lossy())
+            .collect();
+        let glued_discs = order::glued_discs(fnames.iter().map(AsRef::as_ref));
+
+        for (name, disc) in std::mem::take(&mut self.files) {
+            let Some(file) = self.order_file(name, count, glued_discs) else {
+                continue;
+            };
+
+            self.res.push(Audiophile {
+                // the directory a file is in is more trustworthy than its name or tags
+                order: Order {
+                    disc: disc.or(file.order.disc),
+                    ..file.order
+                },
+                ..file
+            });
+        }
+    }
+
+    /// Orders a file by the first order source that knows it, `files` being the number of audio
+    /// files in the album
+    fn order_file(&mut self, name: PathBuf, files: usize, glued_discs: bool) -> Option<Audiophile> {
+        let path = self.dir.join(&name);
+        let mut file = Candidate::new(self.dir, &name, files, glued_discs);
+
+        if self.probe {
+            let res = file.info().map(drop);
+            self.check(&path, Stage::Probe, res)?;
+        }
+
+        for i in 0..self.sources.len() {
+            let res = self.sources[i].position(&mut file);
+
+            let Some(position) = self.check(&path, Stage::Probe, res)? else {
+                continue;
+            };
+
+            let ordered_by = OrderedBy::of(&*self.sources[i]);
+            let info = file.into_info();
+
+            let (order, after) = match position {
+                Position::Numbered(order) => (order, None),
+                Position::After(key) => (
+                    Order {
+                        disc: None,
+                        track: 0,
+                        sub: None,
+                    },
+                    Some(key),
+                ),
+            };
+
+            let file = Audiophile {
+                order,
+                ordered_by,
+                after,
+                name,
+                info,
+            };
+
+            return Some(match self.cross_check {
+                Some(trust) if ordered_by == OrderedBy::Filename => cross_check(file, trust),
+                _ => file,
+            });
+        }
+
+        write_warn(format_args!(
+            "tried to treat `{}` as an audio file, but it could not be ordered",
+            name.display()
+        ));
+
+        None
+    }
+}
+
+/// Compares the order a probed file got from its name with the order in its tags, warning if they
+/// disagree and keeping the one from `trust`
+fn cross_check(file: Audiophile, trust: Trust) -> Audiophile {
+    let Some(tagged) = Order::from_tags(file.info.tags()) else {
+        return file;
+    };
+
+    // a disc only in the tags was already taken by the probe, so only differing ones count
+    if tagged.track == file.order.track && (tagged.disc.is_none() || tagged.disc == file.order.disc)
+    {
+        return file;
+    }
+
+    write_warn(format_args!(
+        "`{}` is numbered {} by its name, but {tagged} by its tags",
+        file.name.display(),
+        file.order
+    ));
+
+    match trust {
+        Trust::Filename => file,
+        Trust::Tags => Audiophile {
+            order: tagged,
+            ordered_by: OrderedBy::Source("tags"),
+            ..file
+        },
+    }
+}
+
+/// Collects all orderable audio files in `dir` and its disc subdirectories (`CD1`, `Disc 2`),
+/// skipping anything ignored by `ignores` or an ignore file in the album, and probing every file
+/// for metadata if `probe` is set
+///
+/// Files that cannot be read are skipped with a diagnostic, only failing to read `dir` itself is
+/// an error
+fn collect_audio_files(
+    dir: &Path,
+    config: &AlbumConfig,
+    ignores: &Ignores,
+    scan: &Scan,
+    probe: bool,
+) -> io::Result<(Vec<Audiophile>, Vec<Diagnostic>)> {
+    let ignores = ignores.with_dir(dir)?;
+
+    let mut collector = Collector {
+        dir,
+        config,
+        ignores: ignores.clone(),
+        exts: scan.extensions(config),
+        sniff: scan.sniff,
+        probe,
+        cross_check: scan.cross_check,
+        sources: source::chain(
+            scan.order_by(config),
+            &config.tracklist,
+            scan.patterns(config),
+            |name| {
+                let custom = scan.custom.iter().find(|c| c.name == name);
+                custom.map(|c| (c.make)())
+            },
+        )?,
+        files: vec![],
+        res: vec![],
+        diagnostics: vec![],
+    };
+
+    let mut discs = vec![];
+
+    for file in fs::read_dir(dir)? {
+        let Some(file) = collector.check(dir, Stage::List, file) else {
+            continue;
+        };
+
+        let Some(file_type) = collector.check(&file.path(), Stage::List, file.file_type()) else {
+            continue;
+        };
+
+        if file_type.is_file() {
+            collector.file(file.file_name().into(), None);
+        } else if file_type.is_dir() {
+            if let Some(disc) = order::disc_dir(&file.file_name().to_string_lossy()) {
+                if !ignores.is_ignored(&file.path(), true) {
+                    discs.push((disc, PathBuf::from(file.file_name())));
+                }
+            }
+        }
+    }
+
+    for (disc, sub) in discs {
+        // disc subdirectories can have ignore files of their own
+        collector.ignores = ignores.with_dir(&dir.join(&sub))?;
+
+        let path = dir.join(&sub);
+
+        let Some(files) = collector.check(&path, Stage::List, fs::read_dir(&path)) else {
+            continue;
+        };
+
+        for file in files {
+            let Some(file) = collector.check(&path, Stage::List, file) else {
+                continue;
+            };
+
+            let Some(file_type) = collector.check(&file.path(), Stage::List, file.file_type())
+            else {
+                continue;
+            };
+
+            if file_type.is_file() {
+                collector.file(sub.join(file.file_name()), Some(disc));
+            }
+        }
+    }
+
+    collector.order_files();
+
+    let Collector {
+        mut res,
+        mut diagnostics,
+        ..
+    } = collector;
+
+    for name in &config.order {
+        if !res.iter().any(|af| af.name == *name) {
+            write_warn(format_args!(
+                "`{}` is in the order of `{}`, but is not in the album",
+                name.display(),
+                dir.join(config::FILENAME).display()
+            ));
+        }
+    }
+
+    // the same track number appearing twice usually means a multi disc album without disc
+    // numbers in its filenames, so every such file has its disc read from tags instead, not just
+    // the colliding ones, as the extra tracks of a longer disc would otherwise sort before disc 1
+    let from_name =
+        |af: &Audiophile| af.ordered_by == OrderedBy::Filename && af.order.disc.is_none();
+
+    if !probe {
+        res.sort_unstable_by_key(Audiophile::rank);
+
+        let dupes = res
+            .windows(2)
+            .any(|w| from_name(&w[0]) && w[0].rank() == w[1].rank());
+
+        for af in res.iter_mut().filter(|af| dupes && from_name(af)) {
+            // the file is kept with the order from its name, its disc is just unknown
+            if let Err(error) = af.probe(dir) {
+                diagnostics.push(Diagnostic {
+                    path: dir.join(&af.name),
+                    stage: Stage::Probe,
+                    error,
+                });
+            }
+        }
+    }
+
+    Ok((res, diagnostics))
+}
+
+/// A simple CLI to generate a playlist from a cdrip'ed album
+#[derive(clap::Parser)]
+#[clap(version, args_conflicts_with_subcommands = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The directory to scan as an album
+    #[arg(default_value = ".")]
+    directory: PathBuf,
+
+    #[command(flatten)]
+    scan: Scan,
+
+    #[command(flatten)]
+    output: Output,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Write a playlist into every album directory of a music library
+    Library {
+        /// The root directory of the library
+        #[arg(default_value = ".")]
+        root: PathBuf,
+
+        #[command(flatten)]
+        scan: Scan,
+
+        #[command(flatten)]
+        output: Output,
+    },
+}
+
+/// Options controlling which files are part of an album
+#[derive(clap::Args)]
+struct Scan {
+    /// extra extensions to treat as audio files, comma separated
+    #[arg(long, value_delimiter = ',')]
+    ext: Vec<String>,
+
+    /// extensions to no longer treat as audio files, comma separated
+    #[arg(long, value_delimiter = ',')]
+    no_ext: Vec<String>,
+
+    /// detect audio files by their content instead of their extension, slower but catches
+    /// missing or wrong extensions
+    #[arg(long)]
+    sniff: bool,
+
+    /// fail instead of skipping files that could not be read
+    #[arg(long)]
+    strict: bool,
+
+    /// read the tags of every file, so the album can be checked against its track and disc
+    /// totals rather than only its own numbering
+    #[arg(long)]
+    check: bool,
+
+    /// read the tags of every file, and warn when a filename and the track tags disagree, keeping
+    /// the order from the given source [default: filename]
+    #[arg(
+        long,
+        value_enum,
+        value_name = "TRUST",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "filename"
+    )]
+    cross_check: Option<Trust>,
+
+    /// where to read the order of a file from, each source is tried in turn until one knows the
+    /// file, comma separated [default: filename,tags,tracklist,natural]
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "SOURCES",
+        value_parser = clap::builder::PossibleValuesParser::new(source::BUILTIN)
+    )]
+    order_by: Vec<String>,
+
+    /// regex for the `regex` order source, matched against the file stem, with a `track` and
+    /// optionally `disc`, `title` and `artist` named groups, can be given more than once to try
+    /// each in turn, the source is tried first by default when any are given
+    #[arg(long)]
+    pattern: Vec<String>,
+
+    /// where to put a hidden pregap track numbered 0 [default: first]
+    #[arg(long, value_enum, value_name = "POLICY")]
+    track_zero: Option<TrackZero>,
+
+    /// order sources of a custom build, tried before the builtin sources of the same name
+    #[arg(skip)]
+    custom: Vec<CustomSource>,
+}
+
+impl Args {
+    /// Parses the command line, with `--order-by` also taking the names of `custom` sources
+    fn parse_with(custom: &[CustomSource]) -> Self {
+        let mut names = source::BUILTIN.to_vec();
+
+        for c in custom {
+            // a custom source can replace a builtin one of the same name
+            if !names.contains(&c.name) {
+                names.push(c.name);
+            }
+        }
+
+        let order_by = |arg: clap::Arg| arg.value_parser(PossibleValuesParser::new(names.clone()));
+
+        let mut command = Self::command()
+            .mut_arg("order_by", order_by)
+            .mut_subcommand("library", |c| c.mut_arg("order_by", order_by));
+
+        let mut args = Self::from_arg_matches(&command.get_matches_mut())
+            .unwrap_or_else(|e| e.format(&mut command).exit());
+
+        args.scan.custom = custom.to_vec();
+
+        if let Some(Command::Library { scan, .. }) = &mut args.command {
+            scan.custom = custom.to_vec();
+        }
+
+        args
+    }
+}
+
+impl Scan {
+    /// The order sources for an album, the command line wins over the album config
+    fn order_by<'a>(&'a self, config: &'a AlbumConfig) -> Vec<&'a str> {
+        if !self.order_by.is_empty() {
+            self.order_by.iter().map(String::as_str).collect()
+        } else if !config.order_by.is_empty() {
+            config.order_by.iter().map(String::as_str).collect()
+        } else if !self.patterns(config).is_empty() {
+            ["regex"]
+                .into_iter()
+                .chain(source::DEFAULT.iter().copied())
+                .collect()
+        } else {
+            source::DEFAULT.to_vec()
+        }
+    }
+
+    /// The regexes for the `regex` order source, the command line wins over the album config
+    fn patterns<'a>(&'a self, config: &'a AlbumConfig) -> &'a [String] {
+        if self.pattern.is_empty() {
+            &config.pattern
+        } else {
+            &self.pattern
+        }
+    }
+
+    /// The audio extensions for an album, the command line wins over the album config
+    fn extensions(&self, config: &AlbumConfig) -> Extensions {
+        Extensions::new()
+            .with(&config.ext, &config.no_ext)
+            .with(&self.ext, &self.no_ext)
+    }
+}
+
+/// Options controlling how a playlist is written
+#[derive(clap::Args)]
+struct Output {
+    /// the filename to output to, or - for stdout [default: playlist.<format>]
+    #[arg(short, long)]
+    outfile: Option<PathBuf>,
+
+    /// the playlist format to write
+    #[arg(short, long, value_enum, default_value_t = playlist::Format::M3u8)]
+    format: playlist::Format,
+
+    /// write an extended m3u8 with #EXTINF durations and titles read from file metadata
+    #[arg(short, long)]
+    extended: bool,
+
+    /// write absolute paths instead of paths relative to the output file
+    #[arg(short, long)]
+    absolute: bool,
+}
+
+impl Output {
+    /// The file to write the playlist of the album in `dir` to, an outfile from the album config
+    /// is relative to the album
+    fn outfile(&self, dir: &Path, config: &AlbumConfig) -> PathBuf {
+        match (&self.outfile, &config.outfile) {
+            (Some(outfile), _) => outfile.clone(),
+            (None, Some(outfile)) => dir.join(outfile),
+            (None, None) => self.format.default_outfile(),
+        }
+    }
+}
+
+/// A scanned album directory
+struct Album {
+    config: AlbumConfig,
+    /// tracks in playlist order
+    tracks: Vec<Audiophile>,
+    /// files that were skipped or only partly read
+    diagnostics: Vec<Diagnostic>,
+    completeness: check::Completeness,
+}
+
+/// Describes every order shared by more than one of the sorted `tracks`, which usually means a
+/// bad rip or a bonus track numbered like a regular one
+fn collisions(tracks: &[Audiophile]) -> Vec<String> {
+    tracks
+        .chunk_by(|a, b| a.rank() == b.rank())
+        .filter(|group| group.len() > 1 && group[0].after.is_none())
+        .map(|group| {
+            let names: Vec<String> = group
+                .iter()
+                .map(|af| format!("`{}`", af.name.display()))
+                .collect();
+
+            format!("track {} is used by {}", group[0].order, names.join(", "))
+        })
+        .collect()
+}
+
+/// Scans `dir` as an album, reading its config and putting its tracks in playlist order,
+/// `ignores` holds the ignore files of the directories above it
+///
+/// Every diagnostic is written as a warning, and fails the scan in strict mode
+fn scan_album(dir: &Path, ignores: &Ignores, scan: &Scan, output: &Output) -> io::Result<Album> {
+    let config = AlbumConfig::load(dir)?;
+
+    let probe =
+        scan.check || scan.cross_check.is_some() || output.format.needs_probe(output.extended);
+    let (mut tracks, diagnostics) = collect_audio_files(dir, &config, ignores, scan, probe)?;
+
+    for diagnostic in &diagnostics {
+        write_warn(diagnostic);
+    }
+
+    if scan.strict && !diagnostics.is_empty() {
+        return Err(io::Error::other(match diagnostics.len() {
+            1 => "1 file could not be read".into(),
+            n => format!("{n} files could not be read"),
+        }));
+    }
+
+    let track_zero = scan.track_zero.or(config.track_zero).unwrap_or_default();
+    let pregap = |af: &Audiophile| af.after.is_none() && af.order.track == 0;
+
+    if track_zero == TrackZero::Exclude {
+        tracks.retain(|af| !pregap(af));
+    }
+
+    let zero_last = |af: &Audiophile| track_zero == TrackZero::Last && pregap(af);
+
+    // files with the same order are put in natural name order, so the result never depends on
+    // the order the directory was listed in
+    tracks.sort_by(|a, b| {
+        let (rank_a, rank_b) = (a.rank(), b.rank());
+
+        // a pregap track put last still stays on its own disc
+        (rank_a.0, rank_a.1.disc, zero_last(a))
+            .cmp(&(rank_b.0, rank_b.1.disc, zero_last(b)))
+            .then(rank_a.cmp(&rank_b))
+            .then_with(|| {
+                order::natural_cmp(
+                    a.name.as_os_str().as_encoded_bytes(),
+                    b.name.as_os_str().as_encoded_bytes(),
+                )
+            })
+    });
+
+    let collisions = collisions(&tracks);
+
+    for collision in &collisions {
+        write_warn(collision);
+    }
+
+    if scan.strict && !collisions.is_empty() {
+        return Err(io::Error::other(match collisions.len() {
+            1 => "1 track number is used more than once".into(),
+            n => format!("{n} track numbers are used more than once"),
+        }));
+    }
+
+    let completeness = check::check(&tracks);
+
+    if !completeness.is_complete() {
+        write_warn(format_args!(
+            "album `{}` is incomplete: {completeness}",
+            dir.display()
+        ));
+    }
+
+    Ok(Album {
+        config,
+        tracks,
+        diagnostics,
+        completeness,
+    })
+}
+
+/// Computes the path of `target` relative to the directory `base`, both must be absolute
+fn relative_to(target: &Path, base: &Path) -> PathBuf {
+    let mut target = target.components().peekable();
+    let mut base = base.components().peekable();
+
+    while target.peek().is_some() && target.peek() == base.peek() {
+        target.next();
+        base.next();
+    }
+
+    base.map(|_| Component::ParentDir).chain(target).collect()
+}
+
+/// Renders the album in `dir` as a playlist and writes it to `outfile`, with `-` meaning stdout
+fn write_playlist(
+    dir: &Path,
+    album: &Album,
+    output: &Output,
+    outfile: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let dir = dir.canonicalize()?;
+    let to_stdout = outfile == Path::new("-");
+
+    // entries are relative to the directory the playlist is in, or the working directory for stdout
+    let base = match outfile.parent() {
+        Some(parent) if !to_stdout && !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+    .canonicalize()?;
+
+    let entries = album
+        .tracks
+        .iter()
+        .map(|af| {
+            let path = dir.join(&af.name);
+
+            let path = if output.absolute {
+                path
+            } else {
+                relative_to(&path, &base)
+            };
+
+            if path.to_str().is_none() {
+                write_warn(format_args!(
+                    "`{}` is not valid UTF-8, it is written as {}",
+                    path.display(),
+                    output.format.non_utf8_paths()
+                ));
+            }
+
+            playlist::Entry { path, track: af }
+        })
+        .collect();
+
+    let playlist = playlist::Playlist {
+        title: album.config.title.as_deref(),
+        entries,
+        diagnostics: &album.diagnostics,
+        completeness: &album.completeness,
+    };
+
+    let out = output.format.render(&playlist, output.extended)?;
+
+    if to_stdout {
+        use io::Write;
+        io::stdout().write_all(&out)?;
+    } else {
+        fs::write(outfile, out)?;
+    }
+
+    Ok(())
+}
+
+/// Runs playlister on the command line, `custom` being the order sources of a custom build, which
+/// are taken by `--order-by` and album configs alongside the builtin ones
+#[must_use]
+pub fn cli(custom: &[CustomSource]) -> ExitCode {
+    match run(custom) {
+        Ok(code) => code,
+        Err(e) => {
+            use io::Write;
+            let _ignore = writeln!(io::stderr().lock(), "\x1b[91mERROR:\x1b[0m {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Exit code for a run that wrote its playlists, but skipped some files or albums
+const EXIT_INCOMPLETE: u8 = 2;
+
+fn run(custom: &[CustomSource]) -> Result<ExitCode, Box<dyn Error>> {
+    let args = Args::parse_with(custom);
+
+    if let Some(Command::Library { root, scan, output }) = args.command {
+        return library::run(&root, &scan, &output);
+    }
+
+    let album = scan_album(
+        &args.directory,
+        &Ignores::default(),
+        &args.scan,
+        &args.output,
+    )?;
+
+    let outfile = args.output.outfile(&args.directory, &album.config);
+
+    // the playlist itself goes to stdout, so it can't be mixed with progress output
+    let to_stdout = outfile == Path::new("-");
+
+    for af in album.tracks.iter().filter(|_| !to_stdout) {
+        use io::Write;
+
+        writeln!(
+            io::stdout(),
+            "\x1b[37mwriting track \x1b[92m#{}\x1b[0m ({}): {}",
+            af.order,
+            af.ordered_by.name(),
+            af.name.display()
+        )?;
+    }
+
+    write_playlist(&args.directory, &album, &args.output, &outfile)?;
+
+    Ok(if album.diagnostics.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_INCOMPLETE)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use clap::Parser;
+
+    use super::{scan_album, Args, Ignores};
+
+    /// An empty directory for the album of a test
+    fn album_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("playlister-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A flac file with nothing but a vorbis comment block holding `fields`
+    fn flac(fields: &[&str]) -> Vec<u8> {
+        let len = |n: usize| u32::try_from(n).unwrap().to_le_bytes();
+
+        let mut block = [&len(0)[..], &len(fields.len())].concat();
+
+        for field in fields {
+            block.extend_from_slice(&len(field.len()));
+            block.extend_from_slice(field.as_bytes());
+        }
+
+        let size = u32::try_from(block.len()).unwrap().to_be_bytes();
+        [&b"fLaC\x84"[..], &size[1..], &block].concat()
+    }
+
+    #[test]
+    fn longer_disc_without_disc_in_names() {
+        let dir = album_dir("longer-disc");
+
+        for (name, disc) in [
+            ("01 a.flac", 1),
+            ("02 b.flac", 1),
+            ("01 c.flac", 2),
+            ("02 d.flac", 2),
+            ("03 e.flac", 2),
+        ] {
+            fs::write(dir.join(name), flac(&[&format!("DISCNUMBER={disc}")])).unwrap();
+        }
+
+        let args = Args::parse_from(["playlister", dir.to_str().unwrap()]);
+        let album = scan_album(&dir, &Ignores::default(), &args.scan, &args.output).unwrap();
+
+        let names: Vec<_> = album
+            .tracks
+            .iter()
+            .map(|af| af.name.to_str().unwrap())
+            .collect();
+
+        assert_eq!(
+            names,
+            [
+                "01 a.flac",
+                "02 b.flac",
+                "01 c.flac",
+                "02 d.flac",
+                "03 e.flac"
+            ]
+        );
+        assert!(album.completeness.is_complete());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,233 @@
+//! Library mode, writing a playlist into every album directory under a root directory
+
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::atomic::Ordering,
+};
+
+use crate::{
+    config::{AlbumConfig, Ignores},
+    is_audio, order, scan_album, write_playlist, write_warn, Output, Scan, EXIT_INCOMPLETE,
+    WARNINGS,
+};
+
+/// Finds every album directory under `dir` that is not ignored, an album being any directory with
+/// audio files or disc subdirectories directly in it, each album is found with the ignore files
+/// of the directories above it
+fn find_albums(dir: &Path, ignores: &Ignores, scan: &Scan, albums: &mut Vec<(PathBuf, Ignores)>) {
+    let parent = ignores;
+
+    let ignores = match ignores.with_dir(dir) {
+        Ok(ignores) => ignores,
+        Err(e) => {
+            write_warn(format_args!("skipping directory `{}`: {e}", dir.display()));
+            return;
+        }
+    };
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            write_warn(format_args!(
+                "could not read directory `{}`: {e}",
+                dir.display()
+            ));
+            return;
+        }
+    };
+
+    let mut is_album = false;
+    let mut files = vec![];
+    let mut subdirs = vec![];
+
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                write_warn(format_args!(
+                    "could not read an entry of `{}`: {e}",
+                    dir.display()
+                ));
+                continue;
+            }
+        };
+
+        // symlinks are never followed, so the walk cannot loop
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+
+        let path = entry.path();
+
+        if ignores.is_ignored(&path, file_type.is_dir()) {
+            continue;
+        }
+
+        if file_type.is_file() {
+            files.push(path);
+        } else if file_type.is_dir() {
+            is_album |= order::disc_dir(&entry.file_name().to_string_lossy()).is_some();
+            subdirs.push(path);
+        }
+    }
+
+    if !is_album && !files.is_empty() {
+        // a broken album config or unreadable file still makes an album, so the error is
+        // reported when it is scanned
+        is_album = match AlbumConfig::load(dir) {
+            Ok(config) => {
+                let exts = scan.extensions(&config);
+                files
+                    .iter()
+                    .any(|file| is_audio(file, &exts, scan.sniff).unwrap_or(true))
+            }
+            Err(_) => true,
+        };
+    }
+
+    if is_album {
+        albums.push((dir.to_owned(), parent.clone()));
+    }
+
+    for sub in subdirs {
+        let name = sub.file_name().unwrap_or_default().to_string_lossy();
+
+        // disc subdirectories are scanned as part of their album
+        if is_album && order::disc_dir(&name).is_some() {
+            continue;
+        }
+
+        find_albums(&sub, &ignores, scan, albums);
+    }
+}
+
+/// The outcome of writing the playlist of an album
+struct Written {
+    tracks: usize,
+    /// whether any files could not be read
+    skipped_files: bool,
+    complete: bool,
+}
+
+/// Writes the playlist of a single album, or returns `None` if it has no orderable tracks,
+/// `outfiles` holds the playlists already written, which are never overwritten by another album
+fn write_album(
+    album: &Path,
+    ignores: &Ignores,
+    scan: &Scan,
+    output: &Output,
+    outfiles: &mut HashSet<PathBuf>,
+) -> Result<Option<Written>, Box<dyn std::error::Error>> {
+    let data = scan_album(album, ignores, scan, output)?;
+
+    if data.tracks.is_empty() {
+        return Ok(None);
+    }
+
+    // every outfile is relative to its album here, including one from the command line
+    let outfile = album.join(
+        output
+            .outfile
+            .as_ref()
+            .or(data.config.outfile.as_ref())
+            .cloned()
+            .unwrap_or_else(|| output.format.default_outfile()),
+    );
+
+    // an outfile like `../artist.m3u8` is shared by every album of an artist
+    let resolved = match (outfile.parent(), outfile.file_name()) {
+        (Some(parent), Some(name)) => parent.canonicalize()?.join(name),
+        _ => outfile.clone(),
+    };
+
+    if !outfiles.insert(resolved) {
+        return Err(format!(
+            "`{}` was already written for another album",
+            outfile.display()
+        )
+        .into());
+    }
+
+    write_playlist(album, &data, output, &outfile)?;
+
+    Ok(Some(Written {
+        tracks: data.tracks.len(),
+        skipped_files: !data.diagnostics.is_empty(),
+        complete: data.completeness.is_complete(),
+    }))
+}
+
+pub fn run(
+    root: &Path,
+    scan: &Scan,
+    output: &Output,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    if output.outfile.as_deref() == Some(Path::new("-")) {
+        return Err("library mode writes a playlist per album, and cannot write to stdout".into());
+    }
+
+    // an absolute path would be the same file for every album, each overwriting the last
+    if output.outfile.as_deref().is_some_and(Path::is_absolute) {
+        return Err(
+            "library mode writes a playlist per album, so its outfile has to be relative".into(),
+        );
+    }
+
+    let mut albums = vec![];
+    find_albums(root, &Ignores::default(), scan, &mut albums);
+    albums.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    let (mut written, mut incomplete, mut skipped, mut warned) = (0usize, 0usize, 0usize, 0usize);
+    let mut skipped_files = false;
+    let mut outfiles = HashSet::new();
+
+    for (album, ignores) in albums {
+        let warnings = WARNINGS.load(Ordering::Relaxed);
+
+        match write_album(&album, &ignores, scan, output, &mut outfiles) {
+            Ok(Some(done)) => {
+                let verdict = if done.complete { "" } else { ", incomplete" };
+
+                writeln!(
+                    io::stdout(),
+                    "\x1b[37mwrote album \x1b[92m({} tracks{verdict})\x1b[0m: {}",
+                    done.tracks,
+                    album.display()
+                )?;
+                written += 1;
+                incomplete += usize::from(!done.complete);
+                skipped_files |= done.skipped_files;
+
+                if WARNINGS.load(Ordering::Relaxed) != warnings {
+                    warned += 1;
+                }
+            }
+            Ok(None) => {
+                write_warn(format_args!(
+                    "skipping album `{}`, none of its files could be ordered",
+                    album.display()
+                ));
+                skipped += 1;
+            }
+            Err(e) => {
+                write_warn(format_args!("skipping album `{}`: {e}", album.display()));
+                skipped += 1;
+            }
+        }
+    }
+
+    writeln!(
+        io::stdout(),
+        "{written} albums written, {incomplete} incomplete, {skipped} skipped, {warned} with warnings"
+    )?;
+
+    Ok(if skipped_files || skipped != 0 {
+        ExitCode::from(EXIT_INCOMPLETE)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
@@ -0,0 +1,537 @@
+//! Sort keys for album tracks, and parsing them out of filenames and tags
+
+use core::{cmp::Ordering, fmt};
+
+/// The position of a track in an album, sorting by disc before track before sub-track
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Order {
+    /// `None` when the album has a single disc, or the disc is not known
+    pub disc: Option<u64>,
+    pub track: u64,
+    /// the letter of a movement numbered like `05a`, sorting after a plain `05`
+    pub sub: Option<char>,
+}
+
+impl fmt::Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(disc) = self.disc {
+            write!(f, "{disc}-")?;
+        }
+
+        write!(f, "{:02}", self.track)?;
+
+        match self.sub {
+            Some(sub) => write!(f, "{sub}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Length of the leading run of ascii digits in `s`
+fn digits(s: &str) -> usize {
+    s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len())
+}
+
+/// Parses vinyl side notation at the start of `s`, `A1` or `B03`, with each side becoming a disc
+/// so `A` is 1 and `D` is 4, returning the order and the length of the notation
+///
+/// Only sides `A` to `H` are taken, enough for a box set of 4 records, as later letters are more
+/// likely names like `U2`
+fn side(s: &str) -> Option<(Order, usize)> {
+    let side = *s.as_bytes().first().filter(|c| (b'A'..=b'H').contains(c))?;
+    let n = digits(&s[1..]);
+
+    // longer numbers are more likely part of a name like `X2000`
+    if !(1..=2).contains(&n) {
+        return None;
+    }
+
+    let order = Order {
+        disc: Some(u64::from(side - b'A') + 1),
+        track: s[1..=n].parse().ok()?,
+        sub: None,
+    };
+
+    Some((order, n + 1))
+}
+
+/// Parses the letter of a movement right after a track number, `05a Allegro` or `05b.flac`, it
+/// has to stand on its own so `1st Movement` has none
+fn sub_track(rest: &str) -> Option<char> {
+    let mut chars = rest.chars();
+    let sub = chars.next().filter(char::is_ascii_lowercase)?;
+
+    chars
+        .next()
+        .is_none_or(|next| !next.is_alphanumeric())
+        .then_some(sub)
+}
+
+impl Order {
+    /// Parses the leading number of a filename, understanding disc prefixes such as
+    /// `1-03 Title.flac`, vinyl sides such as `B3 Title.flac` and movements such as
+    /// `05a Title.flac`, as well as `203 Title.flac` when the album has `glued_discs`
+    pub fn from_filename(fname: &str, glued_discs: bool) -> Option<Self> {
+        let n = digits(fname);
+        let lead = &fname[..n];
+
+        if lead.is_empty() {
+            // the side has to stand on its own, so `MP3 Demo` or `B52s` are not sides
+            let (order, len) = side(fname)?;
+
+            return fname[len..]
+                .chars()
+                .next()
+                .is_none_or(|c| !c.is_alphanumeric())
+                .then_some(order);
+        }
+
+        let dashed = fname[n..].strip_prefix('-').map(digits).filter(|&m| m != 0);
+
+        let (mut order, end) = if let Some(m) = dashed {
+            // `1-03`, a disc number and track number split by a dash
+            let order = Self {
+                disc: Some(lead.parse().ok()?),
+                track: fname[n + 1..n + 1 + m].parse().ok()?,
+                sub: None,
+            };
+
+            (order, n + 1 + m)
+        } else if n == 3 && glued_discs {
+            // `203`, a single digit disc number glued to a 2 digit track number
+            let order = Self {
+                disc: Some(lead[..1].parse().ok()?),
+                track: lead[1..].parse().ok()?,
+                sub: None,
+            };
+
+            (order, n)
+        } else {
+            let order = Self {
+                disc: None,
+                track: lead.parse().ok()?,
+                sub: None,
+            };
+
+            (order, n)
+        };
+
+        order.sub = sub_track(&fname[end..]);
+
+        Some(order)
+    }
+
+    /// Reads the track and disc tags of a file, the track is required but the disc is not
+    pub fn from_tags<'a>(tags: impl Iterator<Item = (&'a str, &'a str)> + Clone) -> Option<Self> {
+        // vinyl rips often have the side in the track tag, which wins over any disc tag
+        if let Some(order) = side_tag(tags.clone()) {
+            return Some(order);
+        }
+
+        Some(Self {
+            disc: disc_tag(tags.clone()),
+            track: tag_number(tags, TRACK_KEYS)?,
+            sub: None,
+        })
+    }
+}
+
+/// Whether the leading numbers of the files of an album are single digit disc numbers glued to 2
+/// digit track numbers, `101` to `112` followed by `201` to `210`
+///
+/// Any other leading number, like the `7` and `100` of a 150 track audiobook, means the 3 digit
+/// ones are plain track numbers, as splitting only some of them would sort `101` before `99`
+pub fn glued_discs<'a>(fnames: impl IntoIterator<Item = &'a str>) -> bool {
+    let mut glued = false;
+
+    for fname in fnames {
+        let n = digits(fname);
+        let lead = &fname[..n];
+
+        // `1-03` has its own disc number, a name without a leading number has none at all and
+        // longer numbers are usually years or catalog numbers, none of which say anything here
+        if n == 0 || n > 3 || fname[n..].starts_with('-') {
+            continue;
+        }
+
+        // zero padded numbering (`001`) and `100` can not be split into a disc and track
+        if n < 3 || lead.starts_with('0') || lead.ends_with("00") {
+            return false;
+        }
+
+        glued = true;
+    }
+
+    glued
+}
+
+/// Why the leading number of a filename does not look like a track number
+pub enum Implausible {
+    /// `2024-05-01 live set`, `20240501 live set`
+    Date,
+    /// `1999 - Party`
+    Year,
+    /// a catalog number or similar, far above the number of files in the album
+    TooLarge { files: usize },
+}
+
+impl fmt::Display for Implausible {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Date => f.write_str("which looks like a date"),
+            Self::Year => f.write_str("which looks like a year"),
+            Self::TooLarge { files } => {
+                write!(f, "which is far above the {files} files in the album")
+            }
+        }
+    }
+}
+
+/// Checks whether `order`, parsed from the start of `fname`, is really a track number, `files`
+/// being the number of audio files in the album
+pub fn implausible(fname: &str, order: Order, files: usize) -> Option<Implausible> {
+    let n = digits(fname);
+    let number = |range: core::ops::Range<usize>| fname.get(range).and_then(|s| s.parse().ok());
+
+    let year = |s: Option<u64>| s.is_some_and(|y| (1900..2100).contains(&y));
+    let month = |s: Option<u64>| s.is_some_and(|m| (1..=12).contains(&m));
+    let day = |s: Option<u64>| s.is_some_and(|d| (1..=31).contains(&d));
+
+    // the same separator between year, month and day
+    let sep = fname.as_bytes().get(4).filter(|c| b"-._".contains(c));
+    let separated = sep.is_some()
+        && fname.as_bytes().get(7) == sep
+        && fname.get(5..).map(digits) == Some(2)
+        && fname.get(8..).map(digits) == Some(2);
+
+    if n == 4 && separated && month(number(5..7)) && day(number(8..10))
+        || n == 8 && year(number(0..4)) && month(number(4..6)) && day(number(6..8))
+    {
+        return Some(Implausible::Date);
+    }
+
+    if n == 4 && year(number(0..4)) {
+        return Some(Implausible::Year);
+    }
+
+    if too_many_tracks(order.track, files) || order.disc.is_some_and(|d| too_many_discs(d, files)) {
+        return Some(Implausible::TooLarge { files });
+    }
+
+    None
+}
+
+/// Whether a track number or total is far above anything an album of `files` audio files has
+#[must_use]
+pub fn too_many_tracks(track: u64, files: usize) -> bool {
+    track > 99 && track > 2 * files as u64
+}
+
+/// Like [`too_many_tracks`], but for disc numbers and totals
+#[must_use]
+pub fn too_many_discs(disc: u64, files: usize) -> bool {
+    disc > 99 && disc > files as u64
+}
+
+/// Keys a track number can be stored under, ffmpeg's generic name followed by the raw vorbis,
+/// id3v2 and mp4 names, in case a backend passed them through unmapped
+const TRACK_KEYS: &[&str] = &["track", "tracknumber", "trck", "trk", "trkn"];
+
+/// Like [`TRACK_KEYS`], but for disc numbers
+const DISC_KEYS: &[&str] = &["disc", "discnumber", "tpos", "tpa", "disk"];
+
+/// Keys a track total can be stored under on its own, rather than as the `/12` of a track tag
+const TRACK_TOTAL_KEYS: &[&str] = &["tracktotal", "totaltracks"];
+
+/// Like [`TRACK_TOTAL_KEYS`], but for disc totals
+const DISC_TOTAL_KEYS: &[&str] = &["disctotal", "totaldiscs"];
+
+/// Parses the disc number out of a disc subdirectory name, like `CD1`, `cd 2` or `Disc 3 - Bonus`
+#[must_use]
+pub fn disc_dir(name: &str) -> Option<u64> {
+    let lower = name.to_ascii_lowercase();
+
+    let rest = ["cd", "disc", "disk"]
+        .into_iter()
+        .find_map(|prefix| lower.strip_prefix(prefix))?
+        .trim_start_matches([' ', '_', '-', '.']);
+
+    rest[..digits(rest)].parse().ok()
+}
+
+/// Reads the disc tag of a file, if it has one
+pub fn disc_tag<'a>(tags: impl Iterator<Item = (&'a str, &'a str)>) -> Option<u64> {
+    tag_number(tags, DISC_KEYS)
+}
+
+/// Reads a track tag in vinyl side notation, `A1` or `B3/6`
+fn side_tag<'a>(tags: impl Iterator<Item = (&'a str, &'a str)>) -> Option<Order> {
+    tags.filter(|(k, _)| TRACK_KEYS.iter().any(|key| k.eq_ignore_ascii_case(key)))
+        .find_map(|(_, v)| {
+            let v = v.split_once('/').map_or(v, |(n, _total)| n).trim();
+            side(v)
+                .filter(|&(_, len)| len == v.len())
+                .map(|(order, _)| order)
+        })
+}
+
+/// Reads the number of tracks on the disc of a file, if its tags say
+pub fn track_total<'a>(tags: impl Iterator<Item = (&'a str, &'a str)> + Clone) -> Option<u64> {
+    tag_total(tags, TRACK_KEYS, TRACK_TOTAL_KEYS)
+}
+
+/// Reads the number of discs in the album of a file, if its tags say
+pub fn disc_total<'a>(tags: impl Iterator<Item = (&'a str, &'a str)> + Clone) -> Option<u64> {
+    tag_total(tags, DISC_KEYS, DISC_TOTAL_KEYS)
+}
+
+/// Reads a total from the `/total` suffix of a tag matching `keys`, or else from a separate tag
+/// matching `total_keys`
+fn tag_total<'a>(
+    tags: impl Iterator<Item = (&'a str, &'a str)> + Clone,
+    keys: &[&str],
+    total_keys: &[&str],
+) -> Option<u64> {
+    tags.clone()
+        .filter(|(k, _)| keys.iter().any(|key| k.eq_ignore_ascii_case(key)))
+        .find_map(|(_, v)| v.split_once('/')?.1.trim().parse().ok())
+        .or_else(|| tag_number(tags, total_keys))
+}
+
+/// Reads the first numeric tag matching any of `keys`, ignoring a `/total` suffix
+fn tag_number<'a>(tags: impl Iterator<Item = (&'a str, &'a str)>, keys: &[&str]) -> Option<u64> {
+    for (k, v) in tags {
+        if keys.iter().any(|key| k.eq_ignore_ascii_case(key)) {
+            let i = v.split_once('/').map_or(v, |(n, _total)| n);
+            if let Ok(n) = i.trim().parse() {
+                return Some(n);
+            }
+        }
+    }
+
+    None
+}
+
+/// Length of the leading run of ascii digits in `b`
+fn digit_run(b: &[u8]) -> usize {
+    b.iter()
+        .position(|c| !c.is_ascii_digit())
+        .unwrap_or(b.len())
+}
+
+fn trim_zeros(run: &[u8]) -> &[u8] {
+    let zeros = run.iter().take_while(|&&c| c == b'0').count();
+    &run[zeros..]
+}
+
+/// Compares names the way a person would, with runs of digits compared by their value, so
+/// `2 Song` sorts before `10 Song`, names that only differ in zero padding fall back to a plain
+/// byte comparison so the order is still total
+#[must_use]
+pub fn natural_cmp(a: &[u8], b: &[u8]) -> Ordering {
+    let (mut rest_a, mut rest_b) = (a, b);
+
+    while let (Some(&ca), Some(&cb)) = (rest_a.first(), rest_b.first()) {
+        if ca.is_ascii_digit() && cb.is_ascii_digit() {
+            let (len_a, len_b) = (digit_run(rest_a), digit_run(rest_b));
+            let (num_a, num_b) = (trim_zeros(&rest_a[..len_a]), trim_zeros(&rest_b[..len_b]));
+
+            // without leading zeros, a longer run is a larger number
+            let ord = num_a.len().cmp(&num_b.len()).then_with(|| num_a.cmp(num_b));
+
+            if ord.is_ne() {
+                return ord;
+            }
+
+            (rest_a, rest_b) = (&rest_a[len_a..], &rest_b[len_b..]);
+        } else {
+            if ca != cb {
+                return ca.cmp(&cb);
+            }
+
+            (rest_a, rest_b) = (&rest_a[1..], &rest_b[1..]);
+        }
+    }
+
+    rest_a.len().cmp(&rest_b.len()).then_with(|| a.cmp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cmp::Ordering;
+
+    use super::{glued_discs, implausible, natural_cmp, side, sub_track, Implausible, Order};
+
+    fn order(disc: Option<u64>, track: u64, sub: Option<char>) -> Order {
+        Order { disc, track, sub }
+    }
+
+    /// Why the leading number of `fname` is not taken, in an album of `files` files
+    fn rule(fname: &str, files: usize) -> Option<Implausible> {
+        implausible(fname, Order::from_filename(fname, false)?, files)
+    }
+
+    #[test]
+    fn years_and_dates_are_implausible() {
+        assert!(matches!(
+            rule("1999 - Party.flac", 12),
+            Some(Implausible::Year)
+        ));
+        assert!(matches!(
+            rule("2024-05-01 live set.flac", 12),
+            Some(Implausible::Date)
+        ));
+        assert!(matches!(
+            rule("2024.05.01 live set.flac", 12),
+            Some(Implausible::Date)
+        ));
+        assert!(matches!(
+            rule("20240501 live set.flac", 12),
+            Some(Implausible::Date)
+        ));
+
+        // mixed separators and impossible months are not dates
+        assert!(matches!(
+            rule("2024-05.01 x.flac", 12),
+            Some(Implausible::Year)
+        ));
+        assert!(matches!(
+            rule("20241301 x.flac", 12),
+            Some(Implausible::TooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn catalog_numbers_are_implausible() {
+        assert!(matches!(
+            rule("4711 x.flac", 12),
+            Some(Implausible::TooLarge { files: 12 })
+        ));
+        assert!(matches!(
+            rule("100 x.flac", 12),
+            Some(Implausible::TooLarge { .. })
+        ));
+
+        // a long audiobook really has that many tracks
+        assert!(rule("100 x.flac", 150).is_none());
+        assert!(rule("07 x.flac", 12).is_none());
+        assert!(rule("1-03 x.flac", 12).is_none());
+    }
+
+    #[test]
+    fn plain_and_dashed_numbers() {
+        assert_eq!(
+            Order::from_filename("07 x.flac", false),
+            Some(order(None, 7, None))
+        );
+        assert_eq!(
+            Order::from_filename("1-03 x.flac", false),
+            Some(order(Some(1), 3, None))
+        );
+        assert_eq!(
+            Order::from_filename("203 x.flac", false),
+            Some(order(None, 203, None))
+        );
+        assert_eq!(Order::from_filename("x.flac", false), None);
+    }
+
+    #[test]
+    fn discs_glued_to_tracks() {
+        let double: Vec<String> = (101..=112)
+            .chain(201..=210)
+            .map(|n| format!("{n} x.flac"))
+            .collect();
+
+        assert!(glued_discs(double.iter().map(String::as_str)));
+        assert_eq!(
+            Order::from_filename("203 x.flac", true),
+            Some(order(Some(2), 3, None))
+        );
+
+        // other numbers next to the 3 digit ones are plain track numbers
+        let audiobook: Vec<String> = (1..=150).map(|n| format!("{n} x.flac")).collect();
+        assert!(!glued_discs(audiobook.iter().map(String::as_str)));
+        assert!(!glued_discs(["7 x.flac", "101 x.flac"]));
+        assert!(!glued_discs(["100 x.flac", "101 x.flac"]));
+        assert!(!glued_discs(["001 x.flac", "002 x.flac"]));
+
+        // names without a leading number, years and disc prefixes say nothing either way
+        assert!(glued_discs([
+            "101 x.flac",
+            "cover.flac",
+            "1999 x.flac",
+            "1-03 x.flac"
+        ]));
+        assert!(!glued_discs(["x.flac", "1-03 x.flac"]));
+    }
+
+    #[test]
+    fn vinyl_sides() {
+        assert_eq!(side("A1 x"), Some((order(Some(1), 1, None), 2)));
+        assert_eq!(side("D12 x"), Some((order(Some(4), 12, None), 3)));
+        assert_eq!(side("H1"), Some((order(Some(8), 1, None), 2)));
+        assert_eq!(side("X2000"), None);
+        assert_eq!(side("A"), None);
+
+        assert_eq!(
+            Order::from_filename("A1 Title.flac", false),
+            Some(order(Some(1), 1, None))
+        );
+        assert_eq!(
+            Order::from_filename("B3.flac", false),
+            Some(order(Some(2), 3, None))
+        );
+
+        // band names that start like a side
+        assert_eq!(
+            Order::from_filename("B52s - Rock Lobster.flac", false),
+            None
+        );
+        assert_eq!(Order::from_filename("U2 - One.flac", false), None);
+        assert_eq!(Order::from_filename("MP3 Demo.flac", false), None);
+    }
+
+    #[test]
+    fn vinyl_sides_in_tags() {
+        let tags = [("track", "B3/6"), ("disc", "1")];
+        assert_eq!(
+            Order::from_tags(tags.into_iter()),
+            Some(order(Some(2), 3, None))
+        );
+
+        let tags = [("TRACKNUMBER", "U2")];
+        assert_eq!(Order::from_tags(tags.into_iter()), None);
+    }
+
+    #[test]
+    fn movements_sort_after_their_track() {
+        assert_eq!(sub_track("a Allegro.flac"), Some('a'));
+        assert_eq!(sub_track("b.flac"), Some('b'));
+        assert_eq!(sub_track("st Movement.flac"), None);
+        assert_eq!(sub_track("A.flac"), None);
+        assert_eq!(sub_track(" a.flac"), None);
+
+        let parse = |fname| Order::from_filename(fname, false).unwrap();
+
+        assert!(parse("05 x.flac") < parse("05a x.flac"));
+        assert!(parse("05a x.flac") < parse("05b x.flac"));
+        assert!(parse("05b x.flac") < parse("06 x.flac"));
+        assert_eq!(parse("1-05a.flac"), order(Some(1), 5, Some('a')));
+        assert_eq!(parse("1st Movement.flac"), order(None, 1, None));
+    }
+
+    #[test]
+    fn natural_order() {
+        let cmp = |a: &str, b: &str| natural_cmp(a.as_bytes(), b.as_bytes());
+
+        assert_eq!(cmp("2 Song", "10 Song"), Ordering::Less);
+        assert_eq!(cmp("Song 9", "Song 10"), Ordering::Less);
+        assert_eq!(cmp("05a", "05b"), Ordering::Less);
+        assert_eq!(cmp("b", "a10"), Ordering::Greater);
+        assert_eq!(cmp("x", "x"), Ordering::Equal);
+
+        // zero padding only breaks ties, so the order stays total
+        assert_eq!(cmp("007", "7"), Ordering::Less);
+        assert_eq!(cmp("007 b", "7 a"), Ordering::Greater);
+    }
+}
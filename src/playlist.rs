@@ -0,0 +1,419 @@
+//! Writers for the supported playlist formats
+
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{check::Completeness, Audiophile, Diagnostic, Stage};
+
+/// A track as written to a playlist
+pub struct Entry<'a> {
+    /// the path written to the playlist, relative to the playlist or absolute
+    pub path: PathBuf,
+    pub track: &'a Audiophile,
+}
+
+/// An album as written to a playlist
+pub struct Playlist<'a> {
+    /// title from the album config, written by the formats that have one
+    pub title: Option<&'a str>,
+    pub entries: Vec<Entry<'a>>,
+    /// files left out of the playlist or only partly read, only written to the scan report
+    pub diagnostics: &'a [Diagnostic],
+    /// only written to the scan report
+    pub completeness: &'a Completeness,
+}
+
+/// The playlist format to write
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// a plain or extended m3u8 playlist
+    M3u8,
+    /// a PLS (version 2) playlist
+    Pls,
+    /// an XSPF (XML shareable playlist format) playlist
+    Xspf,
+    /// a JSPF (JSON XSPF) playlist
+    Jspf,
+    /// a machine readable scan report with the order, its source, codec and tags of every track
+    Json,
+}
+
+impl Format {
+    /// The default output filename for this format
+    pub fn default_outfile(self) -> PathBuf {
+        match self {
+            Self::M3u8 => "playlist.m3u8",
+            Self::Pls => "playlist.pls",
+            Self::Xspf => "playlist.xspf",
+            Self::Jspf => "playlist.jspf",
+            Self::Json => "playlist.json",
+        }
+        .into()
+    }
+
+    /// Whether this format needs metadata from every file, rather than just its order
+    pub fn needs_probe(self, extended: bool) -> bool {
+        match self {
+            Self::M3u8 => extended,
+            Self::Pls | Self::Xspf | Self::Jspf | Self::Json => true,
+        }
+    }
+
+    /// How paths that are not valid UTF-8 end up in this format
+    pub fn non_utf8_paths(self) -> &'static str {
+        match self {
+            Self::M3u8 | Self::Pls => "raw bytes",
+            Self::Xspf | Self::Jspf => "a percent encoded URI",
+            Self::Json => "lossy UTF-8",
+        }
+    }
+
+    /// Renders an already sorted playlist into this format
+    pub fn render(
+        self,
+        playlist: &Playlist,
+        extended: bool,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut out = vec![];
+
+        match self {
+            Self::M3u8 => m3u8(&mut out, playlist, extended)?,
+            Self::Pls => pls(&mut out, &playlist.entries)?,
+            Self::Xspf => xspf(&mut out, playlist)?,
+            Self::Jspf => serde_json::to_writer_pretty(&mut out, &jspf(playlist))?,
+            Self::Json => serde_json::to_writer_pretty(&mut out, &json(playlist))?,
+        }
+
+        Ok(out)
+    }
+}
+
+/// Display title of a track, falling back to its file stem
+fn title(af: &Audiophile) -> String {
+    af.info.label(
+        &af.name
+            .file_stem()
+            .unwrap_or(af.name.as_os_str())
+            .to_string_lossy(),
+    )
+}
+
+/// Writes a path as is, so players on the same system can find files whose names are not UTF-8
+fn write_path(out: &mut Vec<u8>, path: &Path) {
+    out.extend_from_slice(path.as_os_str().as_encoded_bytes());
+}
+
+/// Length of a track in whole seconds, both m3u and pls use -1 for an unknown length
+fn length(af: &Audiophile) -> String {
+    af.info
+        .duration
+        .map_or_else(|| "-1".into(), |d| format!("{:.0}", d.as_secs_f64()))
+}
+
+fn m3u8(out: &mut Vec<u8>, playlist: &Playlist, extended: bool) -> io::Result<()> {
+    if extended {
+        writeln!(out, "#EXTM3U")?;
+
+        if let Some(title) = playlist.title {
+            writeln!(out, "#PLAYLIST:{title}")?;
+        }
+    }
+
+    for Entry { path, track: af } in &playlist.entries {
+        if extended {
+            writeln!(out, "#EXTINF:{},{}", length(af), title(af))?;
+        }
+
+        write_path(out, path);
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+fn pls(out: &mut Vec<u8>, tracks: &[Entry]) -> io::Result<()> {
+    writeln!(out, "[playlist]")?;
+
+    // pls entries are 1 indexed
+    for (n, Entry { path, track: af }) in (1..).zip(tracks) {
+        write!(out, "File{n}=")?;
+        write_path(out, path);
+        writeln!(out)?;
+        writeln!(out, "Title{n}={}", title(af))?;
+        writeln!(out, "Length{n}={}", length(af))?;
+    }
+
+    writeln!(out, "NumberOfEntries={}", tracks.len())?;
+    writeln!(out, "Version=2")
+}
+
+/// Escapes text for use in xml element content or attributes
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => out += "&amp;",
+            '<' => out += "&lt;",
+            '>' => out += "&gt;",
+            '"' => out += "&quot;",
+            '\'' => out += "&apos;",
+            // control characters other than whitespace are not allowed in xml 1.0 at all
+            '\t' | '\n' | '\r' => out.push(c),
+            c if c.is_control() => (),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// Percent encodes a path for use as a URI reference, keeping `/` separators, absolute paths
+/// become `file://` URIs, names that are not UTF-8 are encoded byte for byte
+fn uri_encode(path: &Path) -> String {
+    use core::fmt::Write as _;
+
+    let bytes = path.as_os_str().as_encoded_bytes();
+    let mut out = String::with_capacity(bytes.len());
+
+    if path.is_absolute() {
+        out += "file://";
+    }
+
+    for &b in bytes {
+        if b.is_ascii_alphanumeric() || b"/-._~".contains(&b) {
+            out.push(char::from(b));
+        } else {
+            // writing to a String cannot fail
+            let _ = write!(out, "%{b:02X}");
+        }
+    }
+
+    out
+}
+
+fn xspf(out: &mut Vec<u8>, playlist: &Playlist) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<playlist version="1" xmlns="http://xspf.org/ns/0/">"#
+    )?;
+
+    if let Some(title) = playlist.title {
+        writeln!(out, "  <title>{}</title>", xml_escape(title))?;
+    }
+
+    writeln!(out, "  <trackList>")?;
+
+    for Entry { path, track: af } in &playlist.entries {
+        writeln!(out, "    <track>")?;
+        writeln!(
+            out,
+            "      <location>{}</location>",
+            xml_escape(&uri_encode(path))
+        )?;
+
+        for (elem, tag) in [
+            ("title", "title"),
+            ("creator", "artist"),
+            ("album", "album"),
+        ] {
+            if let Some(v) = af.info.tag(tag) {
+                writeln!(out, "      <{elem}>{}</{elem}>", xml_escape(v))?;
+            }
+        }
+
+        // files sorted after the numbered tracks have no track number to write
+        if af.after.is_none() {
+            writeln!(out, "      <trackNum>{}</trackNum>", af.order.track)?;
+        }
+
+        // xspf durations are in milliseconds
+        if let Some(d) = af.info.duration {
+            writeln!(out, "      <duration>{}</duration>", d.as_millis())?;
+        }
+
+        writeln!(out, "    </track>")?;
+    }
+
+    writeln!(out, "  </trackList>")?;
+    writeln!(out, "</playlist>")
+}
+
+/// Builds a JSPF document, the JSON form of XSPF
+fn jspf(playlist: &Playlist) -> Value {
+    let tracks: Vec<Value> = playlist
+        .entries
+        .iter()
+        .map(|Entry { path, track: af }| {
+            let mut track = serde_json::Map::new();
+
+            track.insert("location".into(), json!([uri_encode(path)]));
+
+            for (elem, tag) in [
+                ("title", "title"),
+                ("creator", "artist"),
+                ("album", "album"),
+            ] {
+                if let Some(v) = af.info.tag(tag) {
+                    track.insert(elem.into(), v.into());
+                }
+            }
+
+            if af.after.is_none() {
+                track.insert("trackNum".into(), af.order.track.into());
+            }
+
+            if let Some(d) = af.info.duration {
+                track.insert("duration".into(), json!(d.as_millis()));
+            }
+
+            Value::Object(track)
+        })
+        .collect();
+
+    let mut doc = serde_json::Map::new();
+
+    if let Some(title) = playlist.title {
+        doc.insert("title".into(), title.into());
+    }
+
+    doc.insert("track".into(), tracks.into());
+
+    json!({ "playlist": doc })
+}
+
+/// The scan report written by [`Format::Json`], changes to its shape should bump `version`
+#[derive(Serialize)]
+struct Report<'a> {
+    version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<&'a str>,
+    tracks: Vec<ReportTrack<'a>>,
+    diagnostics: Vec<ReportDiagnostic<'a>>,
+    completeness: ReportCompleteness,
+}
+
+#[derive(Serialize)]
+struct ReportTrack<'a> {
+    path: Cow<'a, str>,
+    disc: Option<u64>,
+    track: u64,
+    /// the movement letter of a track numbered like `05a`
+    sub: Option<char>,
+    /// false for files sorted after the numbered tracks, whose `track` is always 0
+    numbered: bool,
+    ordered_by: &'a str,
+    codec: Option<&'a str>,
+    /// length in seconds
+    duration: Option<f64>,
+    tags: serde_json::Map<String, Value>,
+}
+
+#[derive(Serialize)]
+struct ReportDiagnostic<'a> {
+    path: Cow<'a, str>,
+    stage: Stage,
+    error: String,
+}
+
+#[derive(Serialize)]
+struct ReportCompleteness {
+    complete: bool,
+    missing: Vec<ReportGap>,
+    extra: Vec<ReportOrder>,
+    missing_discs: Vec<ReportDiscGap>,
+}
+
+/// Tracks `first` to `last` missing from `disc`
+#[derive(Serialize)]
+struct ReportGap {
+    disc: Option<u64>,
+    first: u64,
+    last: u64,
+}
+
+/// Discs `first` to `last` missing from the album
+#[derive(Serialize)]
+struct ReportDiscGap {
+    first: u64,
+    last: u64,
+}
+
+#[derive(Serialize)]
+struct ReportOrder {
+    disc: Option<u64>,
+    track: u64,
+}
+
+fn json<'a>(playlist: &'a Playlist) -> Report<'a> {
+    Report {
+        version: 6,
+        title: playlist.title,
+        tracks: playlist
+            .entries
+            .iter()
+            .map(|Entry { path, track: af }| ReportTrack {
+                path: path.to_string_lossy(),
+                disc: af.order.disc,
+                track: af.order.track,
+                sub: af.order.sub,
+                numbered: af.after.is_none(),
+                ordered_by: af.ordered_by.name(),
+                codec: af.info.codec.as_deref(),
+                duration: af.info.duration.map(|d| d.as_secs_f64()),
+                tags: af
+                    .info
+                    .tags()
+                    .map(|(k, v)| (k.to_owned(), v.into()))
+                    .collect(),
+            })
+            .collect(),
+        diagnostics: playlist
+            .diagnostics
+            .iter()
+            .map(|d| ReportDiagnostic {
+                path: d.path.to_string_lossy(),
+                stage: d.stage,
+                error: d.error.to_string(),
+            })
+            .collect(),
+        completeness: ReportCompleteness {
+            complete: playlist.completeness.is_complete(),
+            missing: playlist
+                .completeness
+                .missing
+                .iter()
+                .map(|gap| ReportGap {
+                    disc: gap.first.disc,
+                    first: gap.first.track,
+                    last: gap.last.track,
+                })
+                .collect(),
+            extra: playlist
+                .completeness
+                .extra
+                .iter()
+                .map(|order| ReportOrder {
+                    disc: order.disc,
+                    track: order.track,
+                })
+                .collect(),
+            missing_discs: playlist
+                .completeness
+                .missing_discs
+                .iter()
+                .map(|gap| ReportDiscGap {
+                    first: gap.first,
+                    last: gap.last,
+                })
+                .collect(),
+        },
+    }
+}
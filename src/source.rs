@@ -0,0 +1,563 @@
+//! Order sources, the places the position of a file in its album can be read from, tried in turn
+//! until one of them knows the file
+
+use std::{
+    any::Any,
+    borrow::Cow,
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use regex::Regex;
+
+use crate::{
+    order::{self, Order},
+    tags::{self, TrackInfo},
+    write_warn,
+};
+
+/// Names of the built in order sources, as given to `--order-by`
+pub const BUILTIN: &[&str] = &[
+    "filename",
+    "tags",
+    "tracklist",
+    "cue",
+    "regex",
+    "natural",
+    "mtime",
+];
+
+/// The order sources tried when neither the command line nor the album config give any, files
+/// without a number anywhere are sorted by name
+pub const DEFAULT: &[&str] = &["filename", "tags", "tracklist", "natural"];
+
+/// Where a source puts a file in the playlist
+#[derive(Debug, Clone, Copy)]
+pub enum Position {
+    /// a track number, sorted with the other numbered tracks
+    Numbered(Order),
+    /// after all numbered tracks, sorted by this key and then by name
+    After(u64),
+}
+
+/// A place the position of a file in its album can be read from
+///
+/// Sources outside this crate implement this and are handed to [`chain`]
+pub trait OrderSource: Any {
+    /// The name of the source, as given to `--order-by` and written to the json report
+    fn name(&self) -> &'static str;
+
+    /// Positions `file`, or returns `None` if this source knows nothing about it, so the next
+    /// source is tried
+    ///
+    /// # Errors
+    ///
+    /// If the file or something next to it could not be read, which skips the file with a
+    /// diagnostic
+    fn position(&mut self, file: &mut Candidate) -> io::Result<Option<Position>>;
+}
+
+/// A file being ordered, its metadata is only read once a source asks for it
+pub struct Candidate<'a> {
+    /// the album directory
+    pub dir: &'a Path,
+    /// relative to the album directory
+    pub name: &'a Path,
+    /// number of audio files in the album
+    pub files: usize,
+    /// whether the album numbers its files like `203`, see [`order::glued_discs`]
+    pub glued_discs: bool,
+    info: Option<TrackInfo>,
+}
+
+impl<'a> Candidate<'a> {
+    #[must_use]
+    pub fn new(dir: &'a Path, name: &'a Path, files: usize, glued_discs: bool) -> Self {
+        Self {
+            dir,
+            name,
+            files,
+            glued_discs,
+            info: None,
+        }
+    }
+
+    #[must_use]
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(self.name)
+    }
+
+    /// The file name, only ascii digits and letters matter for ordering so a lossy one is good
+    /// enough
+    #[must_use]
+    pub fn file_name(&self) -> Cow<'a, str> {
+        self.name.file_name().unwrap_or_default().to_string_lossy()
+    }
+
+    /// Tags and stream info of the file, read on first use
+    ///
+    /// # Errors
+    ///
+    /// If the file could not be probed, see [`tags::probe`]
+    pub fn info(&mut self) -> io::Result<&TrackInfo> {
+        let info = match self.info.take() {
+            Some(info) => info,
+            None => tags::probe(&self.path())?,
+        };
+
+        Ok(self.info.insert(info))
+    }
+
+    /// Tags and stream info of the file, if something already read them
+    #[must_use]
+    pub fn probed(&self) -> Option<&TrackInfo> {
+        self.info.as_ref()
+    }
+
+    /// Gives the file a tag it does not have yet, for the playlist formats that write titles, only
+    /// files whose tags were read get one, as the others are written without titles
+    pub fn add_missing_tag(&mut self, key: &str, value: &str) {
+        if let Some(info) = &mut self.info {
+            if info.tag(key).is_none() && !value.is_empty() {
+                info.tags.push((key.to_owned(), value.to_owned()));
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn into_info(self) -> TrackInfo {
+        self.info.unwrap_or_default()
+    }
+}
+
+/// Builds the order sources named in `names`, in order, `tracklist` being the titles for the
+/// `tracklist` source and `patterns` the regexes for the `regex` source
+///
+/// Every name is first given to `custom`, so callers can add sources of their own or replace
+/// builtin ones, and only names it returns `None` for are looked up in [`BUILTIN`]
+///
+/// # Errors
+///
+/// If a name is neither a custom nor a builtin source, or a pattern is not a valid regex with a
+/// `track` group
+pub fn chain<'n>(
+    names: impl IntoIterator<Item = &'n str>,
+    tracklist: &[String],
+    patterns: &[String],
+    mut custom: impl FnMut(&str) -> Option<Box<dyn OrderSource>>,
+) -> io::Result<Vec<Box<dyn OrderSource>>> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+
+    let mut sources: Vec<Box<dyn OrderSource>> = vec![];
+
+    for name in names {
+        let after_filename = sources.iter().any(|s| is_filename(&**s));
+
+        if let Some(source) = custom(name) {
+            sources.push(source);
+            continue;
+        }
+
+        sources.push(match name {
+            "filename" => Box::new(Filename),
+            "tags" => Box::new(Tags {
+                warn_fallback: after_filename,
+            }),
+            "tracklist" => Box::new(Tracklist(tracklist.to_vec())),
+            "cue" => Box::new(Cue::default()),
+            "regex" => {
+                if patterns.is_empty() {
+                    return Err(invalid("the regex order source needs a --pattern".into()));
+                }
+
+                let mut regexes = vec![];
+
+                for pattern in patterns {
+                    let regex = Regex::new(pattern).map_err(|e| invalid(e.to_string()))?;
+
+                    if !regex.capture_names().any(|group| group == Some("track")) {
+                        return Err(invalid(format!("pattern `{pattern}` has no `track` group")));
+                    }
+
+                    regexes.push(regex);
+                }
+
+                Box::new(Patterns(regexes))
+            }
+            "natural" => Box::new(Natural),
+            "mtime" => Box::new(Mtime),
+            _ => return Err(invalid(format!("unknown order source `{name}`"))),
+        });
+    }
+
+    Ok(sources)
+}
+
+/// Whether `source` is the builtin filename source, rather than a custom one of the same name
+#[must_use]
+pub fn is_filename(source: &dyn OrderSource) -> bool {
+    (source as &dyn Any).is::<Filename>()
+}
+
+/// The leading number of the filename, see [`Order::from_filename`]
+struct Filename;
+
+impl OrderSource for Filename {
+    fn name(&self) -> &'static str {
+        "filename"
+    }
+
+    fn position(&mut self, file: &mut Candidate) -> io::Result<Option<Position>> {
+        let fname = file.file_name();
+
+        let Some(mut order) = Order::from_filename(&fname, file.glued_discs) else {
+            return Ok(None);
+        };
+
+        if let Some(rule) = order::implausible(&fname, order, file.files) {
+            let digits = fname.find(|c: char| !c.is_ascii_digit());
+
+            write_warn(format_args!(
+                "`{}` starts with {}, {rule}, so it is not ordered by its name",
+                file.name.display(),
+                &fname[..digits.unwrap_or(fname.len())]
+            ));
+            return Ok(None);
+        }
+
+        // a disc only in the tags is still better than none, when they were read anyway
+        if let Some(info) = file.probed() {
+            order.disc = order.disc.or_else(|| order::disc_tag(info.tags()));
+        }
+
+        Ok(Some(Position::Numbered(order)))
+    }
+}
+
+/// The track and disc tags
+struct Tags {
+    /// whether to warn, once, that files are read because their names had no number
+    warn_fallback: bool,
+}
+
+impl OrderSource for Tags {
+    fn name(&self) -> &'static str {
+        "tags"
+    }
+
+    fn position(&mut self, file: &mut Candidate) -> io::Result<Option<Position>> {
+        if self.warn_fallback {
+            self.warn_fallback = false;
+            write_warn("falling back to reading tags as filename contains no ordering");
+        }
+
+        Ok(Order::from_tags(file.info()?.tags()).map(Position::Numbered))
+    }
+}
+
+/// The position of the title tag, or else the file stem, in the tracklist of the album config
+struct Tracklist(Vec<String>);
+
+impl OrderSource for Tracklist {
+    fn name(&self) -> &'static str {
+        "tracklist"
+    }
+
+    fn position(&mut self, file: &mut Candidate) -> io::Result<Option<Position>> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+
+        let stem = file.name.file_stem().unwrap_or_default().to_string_lossy();
+        let title = file.info()?.tag("title").unwrap_or(&stem);
+
+        Ok(tracklist_position(&self.0, title).map(|pos| {
+            Position::Numbered(Order {
+                disc: None,
+                track: pos as u64 + 1,
+                sub: None,
+            })
+        }))
+    }
+}
+
+/// Position of the entry of `tracklist` matching `title`, ignoring case, spacing and punctuation,
+/// a title only containing an entry (`Artist - Title`) matches the longest such entry
+fn tracklist_position(tracklist: &[String], title: &str) -> Option<usize> {
+    let normalize = |s: &str| -> String {
+        s.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+
+    let title = normalize(title);
+    let entries: Vec<String> = tracklist.iter().map(|entry| normalize(entry)).collect();
+
+    entries
+        .iter()
+        .position(|entry| *entry == title)
+        .or_else(|| {
+            entries
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| !entry.is_empty() && title.contains(entry.as_str()))
+                .max_by_key(|(_, entry)| entry.len())
+                .map(|(pos, _)| pos)
+        })
+}
+
+/// The track numbers of the cue sheets next to the file, matched by file name or, as cue sheets
+/// often still point at the wav a rip was encoded from, by stem
+#[derive(Default)]
+struct Cue {
+    /// the files and track numbers of every cue sheet, by directory
+    sheets: HashMap<PathBuf, Vec<(String, u64)>>,
+}
+
+/// Reads the `FILE` entries of every cue sheet in `dir`, with the number of the first `TRACK` in
+/// each
+fn read_cue_sheets(dir: &Path) -> io::Result<Vec<(String, u64)>> {
+    let mut entries = vec![];
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if !path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("cue"))
+        {
+            continue;
+        }
+
+        let sheet = fs::read(&path)
+            .map_err(|e| io::Error::new(e.kind(), format!("`{}`: {e}", path.display())))?;
+
+        // cue sheets are often in a legacy encoding, only the ascii keywords have to survive
+        let sheet = String::from_utf8_lossy(&sheet);
+        let mut current = None;
+
+        for line in sheet.lines() {
+            let line = line.trim();
+
+            if let Some(rest) = line.strip_prefix("FILE ") {
+                // `FILE "01 Intro.wav" WAVE`, the name is quoted unless it has no spaces
+                current = match rest.strip_prefix('"') {
+                    Some(quoted) => quoted.split_once('"').map(|(name, _)| name.to_owned()),
+                    None => rest.split_whitespace().next().map(str::to_owned),
+                };
+            } else if let Some(rest) = line.strip_prefix("TRACK ") {
+                let number = rest.split_whitespace().next().and_then(|n| n.parse().ok());
+
+                if let (Some(name), Some(number)) = (current.take(), number) {
+                    entries.push((name, number));
+                }
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+impl OrderSource for Cue {
+    fn name(&self) -> &'static str {
+        "cue"
+    }
+
+    fn position(&mut self, file: &mut Candidate) -> io::Result<Option<Position>> {
+        let path = file.path();
+        let dir = path.parent().unwrap_or(file.dir);
+
+        if !self.sheets.contains_key(dir) {
+            self.sheets.insert(dir.to_owned(), read_cue_sheets(dir)?);
+        }
+
+        let fname = file.file_name();
+        let stem = file.name.file_stem().unwrap_or_default().to_string_lossy();
+        let sheets = &self.sheets[dir];
+
+        // cue sheets can name files by a relative path as well
+        let named = |name: &str, part: fn(&Path) -> Option<&std::ffi::OsStr>, want: &str| {
+            part(Path::new(name)).is_some_and(|part| part.to_string_lossy() == want)
+        };
+
+        let track = sheets
+            .iter()
+            .find(|(name, _)| named(name, Path::file_name, &fname))
+            .or_else(|| {
+                sheets
+                    .iter()
+                    .find(|(name, _)| named(name, Path::file_stem, &stem))
+            })
+            .map(|&(_, track)| track);
+
+        Ok(track.map(|track| {
+            Position::Numbered(Order {
+                disc: None,
+                track,
+                sub: None,
+            })
+        }))
+    }
+}
+
+/// Regexes with a `track` and optionally `disc`, `title` and `artist` groups, matched against the
+/// file stem, the first one that matches with a track number wins
+struct Patterns(Vec<Regex>);
+
+impl OrderSource for Patterns {
+    fn name(&self) -> &'static str {
+        "regex"
+    }
+
+    fn position(&mut self, file: &mut Candidate) -> io::Result<Option<Position>> {
+        let stem = file.name.file_stem().unwrap_or_default().to_string_lossy();
+
+        for regex in &self.0 {
+            let Some(caps) = regex.captures(&stem) else {
+                continue;
+            };
+
+            let number = |group| caps.name(group).and_then(|m| m.as_str().parse().ok());
+
+            let Some(track) = number("track") else {
+                continue;
+            };
+
+            for key in ["title", "artist"] {
+                if let Some(value) = caps.name(key) {
+                    file.add_missing_tag(key, value.as_str().trim());
+                }
+            }
+
+            return Ok(Some(Position::Numbered(Order {
+                disc: number("disc"),
+                track,
+                sub: None,
+            })));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Natural order of the filename, after all numbered tracks
+struct Natural;
+
+impl OrderSource for Natural {
+    fn name(&self) -> &'static str {
+        "natural"
+    }
+
+    fn position(&mut self, file: &mut Candidate) -> io::Result<Option<Position>> {
+        write_warn(format_args!(
+            "`{}` has no track number, so it is sorted by name after the numbered tracks",
+            file.name.display()
+        ));
+
+        Ok(Some(Position::After(0)))
+    }
+}
+
+/// Modification time of the file, after all numbered tracks
+struct Mtime;
+
+impl OrderSource for Mtime {
+    fn name(&self) -> &'static str {
+        "mtime"
+    }
+
+    fn position(&mut self, file: &mut Candidate) -> io::Result<Option<Position>> {
+        let modified = fs::metadata(file.path())?.modified()?;
+
+        write_warn(format_args!(
+            "`{}` has no track number, so it is sorted by modification time after the numbered \
+             tracks",
+            file.name.display()
+        ));
+
+        // files from before 1970 are rare enough to share the first spot
+        let nanos = modified
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| u64::try_from(d.as_nanos()).unwrap_or(u64::MAX));
+
+        Ok(Some(Position::After(nanos)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io};
+
+    use super::{
+        chain, is_filename, read_cue_sheets, tracklist_position, Candidate, OrderSource, Position,
+    };
+
+    /// A custom source that shares its name with the builtin one
+    struct Impostor;
+
+    impl OrderSource for Impostor {
+        fn name(&self) -> &'static str {
+            "filename"
+        }
+
+        fn position(&mut self, _: &mut Candidate) -> io::Result<Option<Position>> {
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn builtin_filename_is_told_apart_by_identity() {
+        let builtin = chain(["filename"], &[], &[], |_| None).unwrap();
+        let custom = chain(["filename"], &[], &[], |_| Some(Box::new(Impostor))).unwrap();
+
+        assert!(is_filename(&*builtin[0]));
+        assert!(!is_filename(&*custom[0]));
+    }
+
+    #[test]
+    fn tracklist_matches_loosely() {
+        let tracklist: Vec<String> = ["Intro", "Song", "Song (Reprise)", "Outro"]
+            .map(String::from)
+            .into();
+
+        assert_eq!(tracklist_position(&tracklist, "intro"), Some(0));
+        assert_eq!(tracklist_position(&tracklist, "Song (reprise)"), Some(2));
+
+        // without an exact match, the longest entry contained in the title wins
+        assert_eq!(
+            tracklist_position(&tracklist, "03 Song Reprise [live]"),
+            Some(2)
+        );
+        assert_eq!(tracklist_position(&tracklist, "Song - Remastered"), Some(1));
+        assert_eq!(tracklist_position(&tracklist, "Bonus"), None);
+    }
+
+    #[test]
+    fn reads_cue_sheets() {
+        let dir = std::env::temp_dir().join(format!("playlister-cue-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let sheet = "REM GENRE Rock\r\n\
+                     FILE \"01 Intro.wav\" WAVE\r\n  TRACK 01 AUDIO\r\n    INDEX 01 00:00:00\r\n\
+                     FILE 02_Song.wav WAVE\r\n  TRACK 02 AUDIO\r\n  TRACK 03 AUDIO\r\n\
+                     FILE \"no track.wav\" WAVE\r\n";
+
+        fs::write(dir.join("album.CUE"), sheet).unwrap();
+        fs::write(dir.join("notes.txt"), "FILE x.wav WAVE\nTRACK 09 AUDIO\n").unwrap();
+
+        let entries = read_cue_sheets(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            entries,
+            [
+                ("01 Intro.wav".to_owned(), 1),
+                ("02_Song.wav".to_owned(), 2)
+            ]
+        );
+    }
+}
@@ -0,0 +1,55 @@
+//! Tag reading through a system ffmpeg
+
+use std::{fs::File, io, path::Path, time::Duration};
+
+use super::TrackInfo;
+
+pub fn probe(file: &Path) -> io::Result<TrackInfo> {
+    let parse = ffmpeg_next::format::input(file)?;
+
+    // ffmpeg reports duration in AV_TIME_BASE (microsecond) units, or AV_NOPTS_VALUE if unknown
+    let duration = u64::try_from(parse.duration())
+        .ok()
+        .filter(|&d| d != 0)
+        .map(Duration::from_micros);
+
+    let stream = parse.streams().best(ffmpeg_next::media::Type::Audio);
+
+    let codec = stream
+        .as_ref()
+        .map(|s| s.parameters().id().name().to_owned());
+
+    let mut tags: Vec<(String, String)> = parse
+        .metadata()
+        .iter()
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect();
+
+    // ogg vorbis and opus keep their vorbis comments on the audio stream rather than the
+    // container, container tags win where both have a key
+    if let Some(stream) = stream {
+        for (k, v) in stream.metadata().iter() {
+            if !tags.iter().any(|(key, _)| key.eq_ignore_ascii_case(k)) {
+                tags.push((k.to_owned(), v.to_owned()));
+            }
+        }
+    }
+
+    Ok(TrackInfo {
+        duration,
+        codec,
+        tags,
+    })
+}
+
+pub fn is_audio(file: &Path) -> io::Result<bool> {
+    // an unreadable file is an error, but anything readable that ffmpeg cannot open is not audio
+    File::open(file)?;
+
+    Ok(ffmpeg_next::format::input(file).is_ok_and(|parse| {
+        parse
+            .streams()
+            .best(ffmpeg_next::media::Type::Audio)
+            .is_some()
+    }))
+}
@@ -0,0 +1,71 @@
+//! Reading tags and stream info out of audio files, either with the builtin reader or with ffmpeg
+//! when built with the `ffmpeg` feature
+
+use std::{io, path::Path, time::Duration};
+
+#[cfg(feature = "ffmpeg")]
+mod ffmpeg;
+#[cfg(not(feature = "ffmpeg"))]
+mod native;
+
+/// Metadata probed from an audio file, empty unless the file was probed
+#[derive(Debug, Default)]
+pub struct TrackInfo {
+    pub duration: Option<Duration>,
+    pub codec: Option<String>,
+    /// tags using ffmpeg's generic key names (`track`, `disc`, `album_artist`), other keys are kept
+    /// as the file has them
+    pub tags: Vec<(String, String)>,
+}
+
+impl TrackInfo {
+    pub fn tags(&self) -> impl Iterator<Item = (&str, &str)> + Clone {
+        self.tags.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    #[must_use]
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+
+    /// `artist - title` for display in playlists, falling back to `fallback` if there is no title
+    #[must_use]
+    pub fn label(&self, fallback: &str) -> String {
+        let label = match (self.tag("artist"), self.tag("title")) {
+            (Some(artist), Some(title)) => format!("{artist} - {title}"),
+            (None, Some(title)) => title.to_owned(),
+            (_, None) => fallback.to_owned(),
+        };
+
+        // a newline would break line based formats
+        label.replace(['\r', '\n'], " ")
+    }
+}
+
+/// Reads the tags, duration and codec of an audio file
+///
+/// # Errors
+///
+/// If the file could not be read, or its tags are malformed
+pub fn probe(file: &Path) -> io::Result<TrackInfo> {
+    #[cfg(feature = "ffmpeg")]
+    return ffmpeg::probe(file);
+
+    #[cfg(not(feature = "ffmpeg"))]
+    return native::probe(file);
+}
+
+/// Whether a file holds audio, judged by its content rather than its name
+///
+/// # Errors
+///
+/// If the file could not be read
+pub fn is_audio(file: &Path) -> io::Result<bool> {
+    #[cfg(feature = "ffmpeg")]
+    return ffmpeg::is_audio(file);
+
+    #[cfg(not(feature = "ffmpeg"))]
+    return native::is_audio(file);
+}
@@ -0,0 +1,174 @@
+//! Apev2 tags, found at the end of monkey's audio, wavpack and musepack files (and some mp3s)
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use super::{invalid, push, read_array, read_vec, take, u32_le, TrackInfo};
+
+/// Maps apev2 item keys onto ffmpeg's generic names
+fn key(item: &str) -> String {
+    let lower = item.to_ascii_lowercase();
+
+    match lower.as_str() {
+        "album artist" | "albumartist" => "album_artist".into(),
+        "year" => "date".into(),
+        _ => lower,
+    }
+}
+
+pub fn read(r: &mut (impl Read + Seek), info: &mut TrackInfo) -> io::Result<()> {
+    let len = r.seek(SeekFrom::End(0))?;
+
+    // the footer either ends the file, or sits directly before an id3v1 tag
+    for end in [len, len.saturating_sub(128)] {
+        let Some(footer_start) = end.checked_sub(32) else {
+            continue;
+        };
+
+        r.seek(SeekFrom::Start(footer_start))?;
+        let footer = read_array::<32>(r)?;
+
+        if &footer[..8] != b"APETAGEX" {
+            continue;
+        }
+
+        // the size covers all items and the footer, but not the optional header
+        let size = u64::from(u32_le(&footer, 12).unwrap_or_default());
+        let count = u32_le(&footer, 16).unwrap_or_default();
+
+        let Some(items_start) = end.checked_sub(size).filter(|_| size >= 32) else {
+            return Err(invalid("bad apev2 tag size"));
+        };
+
+        r.seek(SeekFrom::Start(items_start))?;
+        let items = read_vec(r, size - 32)?;
+        let mut cur = items.as_slice();
+
+        for _ in 0..count {
+            let len = u32::from_le_bytes(read_array(&mut cur)?);
+            let flags = u32::from_le_bytes(read_array(&mut cur)?);
+
+            let key_len = cur
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(|| invalid("truncated apev2 item"))?;
+
+            let item = String::from_utf8_lossy(take(&mut cur, key_len)?).into_owned();
+            take(&mut cur, 1)?;
+
+            let value = take(&mut cur, len as usize)?;
+
+            // bits 1 and 2 hold the item type, where 0 is utf8 text
+            if flags & 0b110 == 0 {
+                let value = String::from_utf8_lossy(value);
+                let values: Vec<&str> = value.split('\0').filter(|v| !v.is_empty()).collect();
+
+                push(info, key(&item), &values.join("; "));
+            }
+        }
+
+        return Ok(());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{super::read_bytes, read};
+
+    fn item(key: &str, flags: u32, value: &[u8]) -> Vec<u8> {
+        let len = u32::try_from(value.len()).unwrap();
+        [
+            &len.to_le_bytes()[..],
+            &flags.to_le_bytes(),
+            key.as_bytes(),
+            &[0],
+            value,
+        ]
+        .concat()
+    }
+
+    /// Items followed by a footer, with `size` and `count` as given
+    fn tag(items: &[Vec<u8>], size: u32, count: u32) -> Vec<u8> {
+        [
+            &items.concat()[..],
+            b"APETAGEX",
+            &2000u32.to_le_bytes(),
+            &size.to_le_bytes(),
+            &count.to_le_bytes(),
+            &[0; 12],
+        ]
+        .concat()
+    }
+
+    /// A well formed tag holding `items`
+    fn whole_tag(items: &[Vec<u8>]) -> Vec<u8> {
+        let size = u32::try_from(items.concat().len() + 32).unwrap();
+        tag(items, size, u32::try_from(items.len()).unwrap())
+    }
+
+    #[test]
+    fn reads_items() {
+        let items = [
+            item("Title", 0, b"Song"),
+            item("Artist", 0, b"A\0B"),
+            item("Year", 0, b"1999"),
+            item("Cover Art (Front)", 0b10, b"\x89PNG"),
+        ];
+
+        let info = read_bytes(read, [vec![0xFF; 64], whole_tag(&items)].concat()).unwrap();
+
+        assert_eq!(info.tag("title"), Some("Song"));
+        assert_eq!(info.tag("artist"), Some("A; B"));
+        assert_eq!(info.tag("date"), Some("1999"));
+        assert_eq!(info.tags.len(), 3);
+    }
+
+    #[test]
+    fn reads_before_id3v1() {
+        let mut id3v1 = vec![0; 128];
+        id3v1[..3].copy_from_slice(b"TAG");
+
+        let info = read_bytes(
+            read,
+            [whole_tag(&[item("Title", 0, b"Song")]), id3v1].concat(),
+        )
+        .unwrap();
+        assert_eq!(info.tag("title"), Some("Song"));
+    }
+
+    #[test]
+    fn no_tag() {
+        assert!(read_bytes(read, vec![0; 300]).unwrap().tags.is_empty());
+        assert!(read_bytes(read, vec![0; 8]).unwrap().tags.is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_sizes() {
+        let items = [item("Title", 0, b"Song")];
+
+        // smaller than the footer itself, and larger than the file
+        for size in [16, 4096, u32::MAX] {
+            let err = read_bytes(read, tag(&items, size, 1)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_items() {
+        // more items than the tag holds
+        let items = [item("Title", 0, b"Song")];
+        let size = u32::try_from(items.concat().len() + 32).unwrap();
+
+        assert!(read_bytes(read, tag(&items, size, 2)).is_err());
+
+        // a value running past the end of the tag
+        let mut long = item("Title", 0, b"Song");
+        long[0] = 200;
+
+        let err = read_bytes(read, whole_tag(&[long])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,396 @@
+//! Id3v2 tags at the start of a file, and id3v1 tags at the end of one
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use super::{invalid, mpeg_codec, push, read_array, read_vec, take, u32_be, TrackInfo};
+
+/// Maps id3v2 frame ids (both the 4 character v2.3/v2.4 ids and 3 character v2.2 ones) onto
+/// ffmpeg's generic names
+fn key(id: &str) -> Option<&'static str> {
+    Some(match id {
+        "TIT2" | "TT2" => "title",
+        "TPE1" | "TP1" => "artist",
+        "TPE2" | "TP2" => "album_artist",
+        "TALB" | "TAL" => "album",
+        "TRCK" | "TRK" => "track",
+        "TPOS" | "TPA" => "disc",
+        "TDRC" | "TYER" | "TYE" => "date",
+        "TCON" | "TCO" => "genre",
+        "TCOM" | "TCM" => "composer",
+        _ => return None,
+    })
+}
+
+/// 28 bit integers stored 7 bits to a byte
+fn syncsafe(b: [u8; 4]) -> u32 {
+    b.into_iter()
+        .fold(0, |acc, b| acc << 7 | u32::from(b & 0x7F))
+}
+
+/// Reverses unsynchronisation, which inserts a zero byte after every 0xFF
+fn unsync(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut prev = 0;
+
+    for &b in data {
+        if !(prev == 0xFF && b == 0) {
+            out.push(b);
+        }
+
+        prev = b;
+    }
+
+    out
+}
+
+/// Decodes the values of an id3v2 text frame
+fn values(data: &[u8]) -> Option<Vec<String>> {
+    let (&encoding, text) = data.split_first()?;
+
+    let decoded = match encoding {
+        0 => text.iter().map(|&b| char::from(b)).collect(),
+        1 | 2 => {
+            // utf16 with a byte order mark, or big endian utf16 without one
+            let (be, text) = match text {
+                [0xFF, 0xFE, rest @ ..] if encoding == 1 => (false, rest),
+                [0xFE, 0xFF, rest @ ..] if encoding == 1 => (true, rest),
+                rest => (encoding == 2, rest),
+            };
+
+            let units = text.chunks_exact(2).map(|c| {
+                let c = [c[0], c[1]];
+                if be {
+                    u16::from_be_bytes(c)
+                } else {
+                    u16::from_le_bytes(c)
+                }
+            });
+
+            char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect()
+        }
+        3 => String::from_utf8_lossy(text).into_owned(),
+        _ => return None,
+    };
+
+    // id3v2.4 separates multiple values with nul, and each utf16 value has its own byte order mark
+    Some(
+        decoded
+            .split('\0')
+            .map(|v| v.trim_start_matches('\u{FEFF}').to_owned())
+            .collect(),
+    )
+}
+
+/// Joins multiple values with `; `, skipping empty ones
+fn join(values: &[String]) -> String {
+    let values: Vec<&str> = values
+        .iter()
+        .map(String::as_str)
+        .filter(|v| !v.is_empty())
+        .collect();
+
+    values.join("; ")
+}
+
+fn frame(id: &str, data: &[u8], info: &mut TrackInfo) {
+    let Some(values) = values(data) else {
+        return;
+    };
+
+    if id == "TXXX" || id == "TXX" {
+        // user defined text, a description followed by the value
+        if let Some((desc, value)) = values.split_first() {
+            push(info, desc.to_ascii_lowercase(), &join(value));
+        }
+    } else if id.starts_with('T') {
+        push(info, key(id).unwrap_or(id), &join(&values));
+    }
+}
+
+pub fn v2(r: &mut (impl Read + Seek), info: &mut TrackInfo) -> io::Result<()> {
+    let [_, _, _, major, _revision, flags, size @ ..] = read_array::<10>(r)?;
+
+    let body = read_vec(r, syncsafe(size).into())?;
+
+    // v2.4 tags can have a copy of the header as a footer
+    if major == 4 && flags & 0x10 != 0 {
+        read_array::<10>(r)?;
+    }
+
+    if let Ok(header) = read_array::<2>(r) {
+        info.codec = mpeg_codec(header).map(Into::into);
+    }
+
+    if !(2..=4).contains(&major) {
+        return Ok(());
+    }
+
+    // before v2.4 unsynchronisation applies to the whole tag, afterwards to each frame
+    let tag_unsync = flags & 0x80 != 0;
+
+    let body = if tag_unsync && major < 4 {
+        unsync(&body)
+    } else {
+        body
+    };
+
+    let mut cur = body.as_slice();
+
+    if flags & 0x40 != 0 {
+        match major {
+            // in v2.2 this flag means compression, which nothing implements
+            2 => return Ok(()),
+            3 => {
+                let len = u32::from_be_bytes(read_array(&mut cur)?);
+                take(&mut cur, len as usize)?;
+            }
+            _ => {
+                let len = syncsafe(read_array(&mut cur)?);
+                take(&mut cur, (len as usize).saturating_sub(4))?;
+            }
+        }
+    }
+
+    loop {
+        let (id, len, frame_flags) = if major == 2 {
+            let Ok([a, b, c, l0, l1, l2]) = read_array::<6>(&mut cur) else {
+                break;
+            };
+
+            (vec![a, b, c], u32::from_be_bytes([0, l0, l1, l2]), 0)
+        } else {
+            let Ok(header) = read_array::<10>(&mut cur) else {
+                break;
+            };
+
+            let len = u32_be(&header, 4).unwrap_or_default();
+            let len = if major == 4 {
+                syncsafe(len.to_be_bytes())
+            } else {
+                len
+            };
+
+            (
+                header[..4].to_vec(),
+                len,
+                u16::from_be_bytes([header[8], header[9]]),
+            )
+        };
+
+        // the rest of the tag is padding
+        if id[0] == 0 {
+            break;
+        }
+
+        let mut data = take(&mut cur, len as usize).map_err(|_| invalid("truncated id3 frame"))?;
+
+        let (compressed, grouped, frame_unsync, length_indicator) = if major == 4 {
+            (
+                frame_flags & 0x000C != 0,
+                frame_flags & 0x0040 != 0,
+                tag_unsync || frame_flags & 0x0002 != 0,
+                frame_flags & 0x0001 != 0,
+            )
+        } else {
+            (
+                frame_flags & 0x00C0 != 0,
+                frame_flags & 0x0020 != 0,
+                false,
+                false,
+            )
+        };
+
+        // compressed and encrypted frames are never text people care about here
+        if compressed {
+            continue;
+        }
+
+        if grouped {
+            take(&mut data, 1)?;
+        }
+
+        if length_indicator {
+            take(&mut data, 4)?;
+        }
+
+        let data = if frame_unsync {
+            unsync(data)
+        } else {
+            data.to_vec()
+        };
+
+        frame(&String::from_utf8_lossy(&id), &data, info);
+    }
+
+    Ok(())
+}
+
+pub fn v1(r: &mut (impl Read + Seek), info: &mut TrackInfo) -> io::Result<()> {
+    if r.seek(SeekFrom::End(0))? < 128 {
+        return Ok(());
+    }
+
+    r.seek(SeekFrom::End(-128))?;
+    let tag = read_array::<128>(r)?;
+
+    if &tag[..3] != b"TAG" {
+        return Ok(());
+    }
+
+    let field = |range: std::ops::Range<usize>| -> String {
+        tag[range]
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| char::from(b))
+            .collect()
+    };
+
+    push(info, "title", &field(3..33));
+    push(info, "artist", &field(33..63));
+    push(info, "album", &field(63..93));
+    push(info, "date", &field(93..97));
+
+    // id3v1.1 steals the last byte of the comment for the track number
+    if tag[125] == 0 && tag[126] != 0 {
+        push(info, "track", &tag[126].to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{super::read_bytes, v1, v2};
+
+    /// A 28 bit syncsafe integer
+    fn syncsafe(n: u32) -> [u8; 4] {
+        [3, 2, 1, 0].map(|i| u8::try_from(n >> (7 * i) & 0x7F).unwrap())
+    }
+
+    /// A v2.3 frame, whose size is a plain big endian integer
+    fn frame(id: [u8; 4], data: &[u8]) -> Vec<u8> {
+        let size = u32::try_from(data.len()).unwrap();
+        [&id[..], &size.to_be_bytes(), &[0, 0], data].concat()
+    }
+
+    fn tag(major: u8, flags: u8, body: &[u8]) -> Vec<u8> {
+        let size = syncsafe(u32::try_from(body.len()).unwrap());
+        [&[b'I', b'D', b'3', major, 0, flags][..], &size, body].concat()
+    }
+
+    #[test]
+    fn reads_v23_text_frames() {
+        let body = [
+            frame(*b"TIT2", b"\0Title"),
+            frame(*b"TRCK", b"\x033/12"),
+            frame(*b"TXXX", b"\x03CATALOG\0ABC-1"),
+            // padding
+            vec![0; 16],
+        ]
+        .concat();
+
+        let info = read_bytes(
+            v2,
+            [tag(3, 0, &body), vec![0xFF, 0xFB, 0x90, 0x00]].concat(),
+        )
+        .unwrap();
+
+        assert_eq!(info.tag("title"), Some("Title"));
+        assert_eq!(info.tag("track"), Some("3/12"));
+        assert_eq!(info.tag("catalog"), Some("ABC-1"));
+        assert_eq!(info.codec.as_deref(), Some("mp3"));
+    }
+
+    #[test]
+    fn reads_utf16_and_multiple_values() {
+        let text: Vec<u8> = "A\0B".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let data = [&[1, 0xFF, 0xFE][..], &text].concat();
+
+        let size = syncsafe(u32::try_from(data.len()).unwrap());
+        let frame = [&b"TPE1"[..], &size, &[0, 0], &data].concat();
+
+        let info = read_bytes(v2, tag(4, 0, &frame)).unwrap();
+        assert_eq!(info.tag("artist"), Some("A; B"));
+    }
+
+    #[test]
+    fn reads_v22_frames() {
+        let body = [&b"TT2"[..], &[0, 0, 6], b"\0Title"].concat();
+
+        let info = read_bytes(v2, tag(2, 0, &body)).unwrap();
+        assert_eq!(info.tag("title"), Some("Title"));
+    }
+
+    #[test]
+    fn removes_unsynchronisation() {
+        // the frame size counts the bytes after the zero byte following 0xFF is removed
+        let body = [&b"TIT2\0\0\0\x03\0\0"[..], b"\0\xFF\0x"].concat();
+
+        let info = read_bytes(v2, tag(3, 0x80, &body)).unwrap();
+        assert_eq!(info.tag("title"), Some("\u{FF}x"));
+    }
+
+    #[test]
+    fn rejects_truncated_frame() {
+        let mut body = frame(*b"TIT2", b"\0Title");
+        body.truncate(body.len() - 2);
+
+        let err = read_bytes(v2, tag(3, 0, &body)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_truncated_tag() {
+        let mut file = tag(3, 0, &frame(*b"TIT2", b"\0Title"));
+        file.truncate(file.len() - 2);
+
+        let err = read_bytes(v2, file).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn rejects_oversized_tag() {
+        let file = [&b"ID3\x03\0\0"[..], &[0x7F; 4]].concat();
+
+        let err = read_bytes(v2, file).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_oversized_extended_header() {
+        let body = [&u32::MAX.to_be_bytes()[..], &frame(*b"TIT2", b"\0Title")].concat();
+
+        assert!(read_bytes(v2, tag(3, 0x40, &body)).is_err());
+    }
+
+    fn v1_tag(track: u8) -> Vec<u8> {
+        let mut tag = vec![0; 128];
+        tag[..3].copy_from_slice(b"TAG");
+        tag[3..8].copy_from_slice(b"Title");
+        tag[93..97].copy_from_slice(b"1999");
+        tag[126] = track;
+        tag
+    }
+
+    #[test]
+    fn reads_v1() {
+        let info = read_bytes(v1, [vec![0xFF; 64], v1_tag(7)].concat()).unwrap();
+
+        assert_eq!(info.tag("title"), Some("Title"));
+        assert_eq!(info.tag("date"), Some("1999"));
+        assert_eq!(info.tag("track"), Some("7"));
+        assert_eq!(info.tag("artist"), None);
+    }
+
+    #[test]
+    fn v1_needs_a_whole_tag() {
+        let mut file = v1_tag(7);
+        file.remove(0);
+
+        assert!(read_bytes(v1, file).unwrap().tags.is_empty());
+    }
+}
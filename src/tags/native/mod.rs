@@ -0,0 +1,193 @@
+//! The builtin tag reader, supporting vorbis comments (flac, ogg vorbis, opus and speex), id3v2
+//! and id3v1 (mp3), mp4 atoms (m4a, alac) and apev2 (ape, wavpack, musepack and others)
+
+mod ape;
+mod id3;
+mod mp4;
+mod vorbis;
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek},
+    path::Path,
+    time::Duration,
+};
+
+use super::TrackInfo;
+
+/// Largest single tag structure that will be read into memory, embedded cover art included
+const MAX_BLOCK: u64 = 64 * 1024 * 1024;
+
+pub fn probe(file: &Path) -> io::Result<TrackInfo> {
+    let mut r = BufReader::new(File::open(file)?);
+    let mut info = TrackInfo::default();
+
+    let mut magic = Vec::with_capacity(12);
+    (&mut r).take(12).read_to_end(&mut magic)?;
+    r.rewind()?;
+
+    match magic.as_slice() {
+        [b'f', b'L', b'a', b'C', ..] => vorbis::flac(&mut r, &mut info)?,
+        [b'O', b'g', b'g', b'S', ..] => vorbis::ogg(&mut r, &mut info)?,
+        [b'I', b'D', b'3', ..] => id3::v2(&mut r, &mut info)?,
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => mp4::read(&mut r, &mut info)?,
+        [b'M', b'A', b'C', b' ', ..] => info.codec = Some("ape".into()),
+        [b'w', b'v', b'p', b'k', ..] => info.codec = Some("wavpack".into()),
+        [b'M', b'P', b'C', b'K', ..] | [b'M', b'P', b'+', ..] => {
+            info.codec = Some("musepack".into());
+        }
+        &[a, b, ..] => info.codec = mpeg_codec([a, b]).map(Into::into),
+        _ => (),
+    }
+
+    // formats without their own tag container, and mp3s without id3v2 tags, usually have apev2
+    // or id3v1 tags at the end of the file
+    if info.tags.is_empty() {
+        ape::read(&mut r, &mut info)?;
+    }
+
+    if info.tags.is_empty() {
+        id3::v1(&mut r, &mut info)?;
+    }
+
+    Ok(info)
+}
+
+/// Classifies a file as audio or not by its content, ignoring its name
+pub fn is_audio(file: &Path) -> io::Result<bool> {
+    let mut r = BufReader::new(File::open(file)?);
+
+    // matroska keeps its track list near the start, so this is enough to find the track types
+    let mut head = Vec::with_capacity(64 * 1024);
+    (&mut r).take(64 * 1024).read_to_end(&mut head)?;
+
+    Ok(match head.as_slice() {
+        [b'f', b'L', b'a', b'C', ..]
+        | [b'I', b'D', b'3', ..]
+        | [b'M', b'A', b'C', b' ', ..]
+        | [b'w', b'v', b'p', b'k', ..]
+        | [b'M', b'P', b'C', b'K', ..]
+        | [b'M', b'P', b'+', ..]
+        | [b'T', b'T', b'A', b'1', ..]
+        | [b'D', b'S', b'D', b' ', ..]
+        | [b'F', b'R', b'M', b'8', ..]
+        | [b'c', b'a', b'f', b'f', ..]
+        | [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..]
+        | [b'F', b'O', b'R', b'M', _, _, _, _, b'A', b'I', b'F', b'F' | b'C', ..]
+        // the asf header object guid, used by wma
+        | [0x30, 0x26, 0xB2, 0x75, 0x8E, 0x66, 0xCF, 0x11, ..] => true,
+        // ogg and mp4 can hold video only, but only get a codec from an audio stream
+        [b'O', b'g', b'g', b'S', ..] | [_, _, _, _, b'f', b't', b'y', b'p', ..] => {
+            probe(file).is_ok_and(|info| info.codec.is_some())
+        }
+        // an ebml header, where a TrackType element (0x83) of 2 marks an audio track
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => head.windows(3).any(|w| w == [0x83, 0x81, 0x02]),
+        &[a, b, ..] => mpeg_codec([a, b]).is_some(),
+        _ => false,
+    })
+}
+
+/// Identifies raw mpeg audio (mp3) and adts (aac) streams by their frame sync
+fn mpeg_codec(header: [u8; 2]) -> Option<&'static str> {
+    match header {
+        // adts has the layer bits set to 0, which is reserved in mpeg audio
+        [0xFF, b] if b & 0xF6 == 0xF0 => Some("aac"),
+        [0xFF, b] if b & 0xE0 == 0xE0 && b & 0x06 != 0 => Some("mp3"),
+        _ => None,
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_array<const N: usize>(r: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Reads `len` bytes, refusing to allocate for anything larger than [`MAX_BLOCK`]
+fn read_vec(r: &mut impl Read, len: u64) -> io::Result<Vec<u8>> {
+    if len > MAX_BLOCK {
+        return Err(invalid("tag block is too large"));
+    }
+
+    let mut buf = vec![];
+    r.take(len).read_to_end(&mut buf)?;
+
+    if buf.len() as u64 == len {
+        Ok(buf)
+    } else {
+        Err(io::ErrorKind::UnexpectedEof.into())
+    }
+}
+
+/// Splits the first `n` bytes off of `cur`
+fn take<'a>(cur: &mut &'a [u8], n: usize) -> io::Result<&'a [u8]> {
+    if cur.len() < n {
+        return Err(invalid("truncated tag"));
+    }
+
+    let (head, tail) = cur.split_at(n);
+    *cur = tail;
+    Ok(head)
+}
+
+fn array_at<const N: usize>(b: &[u8], at: usize) -> Option<[u8; N]> {
+    b.get(at..at.checked_add(N)?)?.try_into().ok()
+}
+
+fn u16_le(b: &[u8], at: usize) -> Option<u16> {
+    array_at(b, at).map(u16::from_le_bytes)
+}
+
+fn u32_le(b: &[u8], at: usize) -> Option<u32> {
+    array_at(b, at).map(u32::from_le_bytes)
+}
+
+fn u64_le(b: &[u8], at: usize) -> Option<u64> {
+    array_at(b, at).map(u64::from_le_bytes)
+}
+
+fn u16_be(b: &[u8], at: usize) -> Option<u16> {
+    array_at(b, at).map(u16::from_be_bytes)
+}
+
+fn u32_be(b: &[u8], at: usize) -> Option<u32> {
+    array_at(b, at).map(u32::from_be_bytes)
+}
+
+fn u64_be(b: &[u8], at: usize) -> Option<u64> {
+    array_at(b, at).map(u64::from_be_bytes)
+}
+
+/// Length of `samples` at `rate` samples per second
+fn samples_duration(samples: u64, rate: u32) -> Option<Duration> {
+    if samples == 0 || rate == 0 {
+        return None;
+    }
+
+    let nanos = u128::from(samples) * 1_000_000_000 / u128::from(rate);
+
+    u64::try_from(nanos).ok().map(Duration::from_nanos)
+}
+
+/// Adds a tag, trimming padding and skipping it entirely if it is empty
+fn push(info: &mut TrackInfo, key: impl Into<String>, value: &str) {
+    let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+
+    if !value.is_empty() {
+        info.tags.push((key.into(), value.to_owned()));
+    }
+}
+
+/// Runs the tag reader `read` over `file` held in memory, for the tests of each format
+#[cfg(test)]
+fn read_bytes(
+    read: impl FnOnce(&mut io::Cursor<Vec<u8>>, &mut TrackInfo) -> io::Result<()>,
+    file: Vec<u8>,
+) -> io::Result<TrackInfo> {
+    let mut info = TrackInfo::default();
+    read(&mut io::Cursor::new(file), &mut info).map(|()| info)
+}
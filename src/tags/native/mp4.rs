@@ -0,0 +1,304 @@
+//! Mp4 (m4a, alac) metadata, stored as iTunes style `ilst` atoms inside the `moov` atom
+
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    iter,
+};
+
+use super::{
+    invalid, push, read_array, read_vec, samples_duration, u16_be, u32_be, u64_be, TrackInfo,
+};
+
+/// Iterates over the atoms packed in `data`, yielding their type and contents, stopping at the
+/// first malformed one
+fn atoms(mut data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    iter::from_fn(move || {
+        let kind = data.get(4..8)?.try_into().ok()?;
+
+        let (size, header) = match u32_be(data, 0)? {
+            1 => (usize::try_from(u64_be(data, 8)?).ok()?, 16),
+            // a size of 0 means the atom runs to the end
+            0 => (data.len(), 8),
+            size => (usize::try_from(size).ok()?, 8),
+        };
+
+        let body = data.get(header..size)?;
+        data = &data[size..];
+
+        Some((kind, body))
+    })
+}
+
+/// Finds the first child atom of type `kind`
+fn child(data: &[u8], kind: [u8; 4]) -> Option<&[u8]> {
+    atoms(data).find(|(k, _)| *k == kind).map(|(_, body)| body)
+}
+
+/// Maps ilst item atoms onto ffmpeg's generic names
+fn key(kind: [u8; 4]) -> Option<&'static str> {
+    Some(match &kind {
+        b"\xA9nam" => "title",
+        b"\xA9ART" => "artist",
+        b"aART" => "album_artist",
+        b"\xA9alb" => "album",
+        b"\xA9day" => "date",
+        b"\xA9gen" => "genre",
+        b"\xA9wrt" => "composer",
+        b"trkn" => "track",
+        b"disk" => "disc",
+        _ => return None,
+    })
+}
+
+/// Reads the payload and type of the `data` atom inside an ilst item
+fn item_data(item: &[u8]) -> Option<(u32, &[u8])> {
+    let data = child(item, *b"data")?;
+
+    // a version byte and 24 bits of type, then 4 bytes of locale
+    Some((u32_be(data, 0)? & 0xFF_FFFF, data.get(8..)?))
+}
+
+fn ilst(ilst: &[u8], info: &mut TrackInfo) {
+    for (kind, item) in atoms(ilst) {
+        let Some((data_type, data)) = item_data(item) else {
+            continue;
+        };
+
+        match &kind {
+            // binary track and disc numbers, as padding, number and total
+            b"trkn" | b"disk" => {
+                let (Some(n), total) = (u16_be(data, 2), u16_be(data, 4)) else {
+                    continue;
+                };
+
+                let value = match total {
+                    Some(total) if total != 0 => format!("{n}/{total}"),
+                    _ => n.to_string(),
+                };
+
+                push(info, key(kind).unwrap_or_default(), &value);
+            }
+            // freeform items, named by a `name` atom with 4 bytes of version and flags
+            b"----" => {
+                let name = child(item, *b"name").and_then(|n| n.get(4..));
+
+                if let (Some(name), 1) = (name, data_type) {
+                    let name = String::from_utf8_lossy(name).to_ascii_lowercase();
+                    push(info, name, &String::from_utf8_lossy(data));
+                }
+            }
+            // type 1 is utf8 text
+            _ => {
+                if let (Some(key), 1) = (key(kind), data_type) {
+                    push(info, key, &String::from_utf8_lossy(data));
+                }
+            }
+        }
+    }
+}
+
+/// Names the codec of the first audio track, from its sample description
+fn codec(moov: &[u8]) -> Option<String> {
+    atoms(moov)
+        .filter(|(k, _)| k == b"trak")
+        .find_map(|(_, trak)| {
+            let mdia = child(trak, *b"mdia")?;
+
+            // version and flags, predefined, then the handler type
+            if child(mdia, *b"hdlr")?.get(8..12)? != b"soun" {
+                return None;
+            }
+
+            let stbl = child(child(mdia, *b"minf")?, *b"stbl")?;
+
+            // version and flags and entry count, then the first entry
+            let entry = child(stbl, *b"stsd")?.get(8..)?;
+            let format = entry.get(4..8)?;
+
+            Some(match format {
+                b"mp4a" => "aac".into(),
+                b"alac" => "alac".into(),
+                b"fLaC" => "flac".into(),
+                b"Opus" => "opus".into(),
+                b".mp3" => "mp3".into(),
+                other => String::from_utf8_lossy(other).trim().to_ascii_lowercase(),
+            })
+        })
+}
+
+fn moov(moov: &[u8], info: &mut TrackInfo) {
+    if let Some(mvhd) = child(moov, *b"mvhd") {
+        // version 1 uses 64 bit times and duration
+        let (timescale, duration) = match mvhd.first() {
+            Some(1) => (u32_be(mvhd, 20), u64_be(mvhd, 24)),
+            _ => (u32_be(mvhd, 12), u32_be(mvhd, 16).map(u64::from)),
+        };
+
+        if let (Some(timescale), Some(duration)) = (timescale, duration) {
+            info.duration = samples_duration(duration, timescale);
+        }
+    }
+
+    info.codec = codec(moov);
+
+    let Some(meta) = child(moov, *b"udta").and_then(|udta| child(udta, *b"meta")) else {
+        return;
+    };
+
+    // iso meta atoms have 4 bytes of version and flags first, quicktime ones go straight to the
+    // children
+    let meta = match meta.get(4..8) {
+        Some(b"hdlr") => meta,
+        _ => meta.get(4..).unwrap_or_default(),
+    };
+
+    if let Some(items) = child(meta, *b"ilst") {
+        ilst(items, info);
+    }
+}
+
+pub fn read(r: &mut (impl Read + Seek), info: &mut TrackInfo) -> io::Result<()> {
+    let len = r.seek(SeekFrom::End(0))?;
+    let mut pos = 0;
+
+    // only the top level atoms are walked from disk, so the media data is never read
+    while pos < len {
+        r.seek(SeekFrom::Start(pos))?;
+
+        let header = read_array::<8>(r)?;
+
+        let (size, header_len) = match u32_be(&header, 0).unwrap_or_default() {
+            1 => (u64::from_be_bytes(read_array(r)?), 16),
+            0 => (len - pos, 8),
+            size => (size.into(), 8),
+        };
+
+        if size < header_len {
+            return Err(invalid("bad mp4 atom size"));
+        }
+
+        if &header[4..] == b"moov" {
+            moov(&read_vec(r, size - header_len)?, info);
+            return Ok(());
+        }
+
+        pos = pos
+            .checked_add(size)
+            .ok_or_else(|| invalid("bad mp4 atom size"))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{super::read_bytes, read};
+
+    fn atom(kind: [u8; 4], body: &[u8]) -> Vec<u8> {
+        let size = u32::try_from(body.len() + 8).unwrap();
+        [&size.to_be_bytes()[..], &kind, body].concat()
+    }
+
+    fn ftyp() -> Vec<u8> {
+        atom(*b"ftyp", b"M4A \0\0\0\0")
+    }
+
+    /// An ilst item holding a `data` atom of `data_type`
+    fn item(kind: [u8; 4], data_type: u8, payload: &[u8]) -> Vec<u8> {
+        atom(
+            kind,
+            &atom(
+                *b"data",
+                &[&[0, 0, 0, data_type, 0, 0, 0, 0], payload].concat(),
+            ),
+        )
+    }
+
+    /// A moov atom with a title and track number, followed by the raw `extra` ilst items
+    fn moov(extra: &[u8]) -> Vec<u8> {
+        // version 0, created and modified, then a timescale of 1000 and a duration of 5000
+        let mut mvhd = vec![0; 12];
+        mvhd.extend_from_slice(&1000u32.to_be_bytes());
+        mvhd.extend_from_slice(&5000u32.to_be_bytes());
+
+        let ilst = atom(
+            *b"ilst",
+            &[
+                item(*b"\xA9nam", 1, b"Song"),
+                item(*b"trkn", 0, &[0, 0, 0, 3, 0, 12, 0, 0]),
+                extra.to_vec(),
+            ]
+            .concat(),
+        );
+
+        let meta = atom(
+            *b"meta",
+            &[&[0; 4][..], &atom(*b"hdlr", &[0; 24]), &ilst].concat(),
+        );
+
+        atom(
+            *b"moov",
+            &[atom(*b"mvhd", &mvhd), atom(*b"udta", &meta)].concat(),
+        )
+    }
+
+    #[test]
+    fn reads_ilst_and_duration() {
+        let info =
+            read_bytes(read, [ftyp(), atom(*b"mdat", &[0; 16]), moov(&[])].concat()).unwrap();
+
+        assert_eq!(info.tag("title"), Some("Song"));
+        assert_eq!(info.tag("track"), Some("3/12"));
+        assert_eq!(info.duration.map(|d| d.as_secs()), Some(5));
+    }
+
+    #[test]
+    fn rejects_oversized_atom() {
+        // a 64 bit size that runs past the end of the address space
+        let mut huge = 1u32.to_be_bytes().to_vec();
+        huge.extend_from_slice(b"free");
+        huge.extend_from_slice(&(u64::MAX - 8).to_be_bytes());
+
+        let err = read_bytes(read, [ftyp(), huge].concat()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_atom_smaller_than_its_header() {
+        let mut bad = 4u32.to_be_bytes().to_vec();
+        bad.extend_from_slice(b"free");
+
+        let err = read_bytes(read, [ftyp(), bad].concat()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_moov() {
+        let mut file = [ftyp(), moov(&[])].concat();
+        file.truncate(file.len() - 10);
+
+        let err = read_bytes(read, file).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn truncated_header() {
+        let mut file = ftyp();
+        file.extend_from_slice(&[0, 0, 0]);
+
+        assert!(read_bytes(read, file).is_err());
+    }
+
+    #[test]
+    fn malformed_items_end_the_ilst() {
+        // an item claiming more than its parent holds ends the walk without failing the file
+        let bad = [&256u32.to_be_bytes()[..], b"\xA9ART", b"Artist"].concat();
+
+        let info = read_bytes(read, [ftyp(), moov(&bad)].concat()).unwrap();
+
+        assert_eq!(info.tag("title"), Some("Song"));
+        assert_eq!(info.tag("artist"), None);
+    }
+}
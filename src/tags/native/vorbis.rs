@@ -0,0 +1,352 @@
+//! Vorbis comments, and the flac and ogg containers that carry them
+
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    mem,
+};
+
+use super::{
+    invalid, push, read_array, read_vec, samples_duration, take, u16_le, u32_le, u64_be, u64_le,
+    TrackInfo, MAX_BLOCK,
+};
+
+/// Maps vorbis comment field names onto ffmpeg's generic names
+fn key(field: &str) -> String {
+    let lower = field.to_ascii_lowercase();
+
+    match lower.as_str() {
+        "tracknumber" => "track".into(),
+        "discnumber" => "disc".into(),
+        "albumartist" | "album artist" => "album_artist".into(),
+        _ => lower,
+    }
+}
+
+/// Parses a bare vorbis comment block, without any packet header or framing bit
+fn comments(mut cur: &[u8], info: &mut TrackInfo) -> io::Result<()> {
+    let len = |cur: &mut &[u8]| -> io::Result<usize> {
+        usize::try_from(u32::from_le_bytes(read_array(cur)?)).map_err(|_| invalid("bad length"))
+    };
+
+    let vendor = len(&mut cur)?;
+    take(&mut cur, vendor)?;
+
+    for _ in 0..len(&mut cur)? {
+        let field = len(&mut cur)?;
+        let field = String::from_utf8_lossy(take(&mut cur, field)?);
+
+        if let Some((k, v)) = field.split_once('=') {
+            push(info, key(k), v);
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the sample rate and total sample count out of a flac STREAMINFO block
+fn streaminfo(block: &[u8]) -> Option<(u32, u64)> {
+    // 20 bits of sample rate, 3 of channels, 5 of bits per sample, then 36 of total samples
+    let packed = u64_be(block, 10)?;
+
+    let rate = u32::try_from(packed >> 44).ok()?;
+    let samples = packed & 0xF_FFFF_FFFF;
+
+    Some((rate, samples))
+}
+
+pub fn flac(r: &mut (impl Read + Seek), info: &mut TrackInfo) -> io::Result<()> {
+    info.codec = Some("flac".into());
+
+    r.seek(SeekFrom::Start(4))?;
+
+    loop {
+        let [kind, len @ ..] = read_array::<4>(r)?;
+        let len = u32::from_be_bytes([0, len[0], len[1], len[2]]);
+
+        match kind & 0x7F {
+            0 => {
+                if let Some((rate, samples)) = streaminfo(&read_vec(r, len.into())?) {
+                    info.duration = samples_duration(samples, rate);
+                }
+            }
+            4 => comments(&read_vec(r, len.into())?, info)?,
+            // pictures and seektables can be large, and are not needed
+            _ => {
+                r.seek(SeekFrom::Current(len.into()))?;
+            }
+        }
+
+        // the high bit marks the last metadata block
+        if kind & 0x80 != 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads the first `n` packets of the first logical stream in an ogg file, returning them with
+/// the serial number of that stream
+fn packets(r: &mut impl Read, n: usize) -> io::Result<(u32, Vec<Vec<u8>>)> {
+    let mut serial = None;
+    let mut packets = vec![];
+    let mut packet = vec![];
+
+    loop {
+        let header = read_array::<27>(r)?;
+
+        if &header[..4] != b"OggS" {
+            return Err(invalid("bad ogg page"));
+        }
+
+        let page_serial = u32_le(&header, 14).unwrap_or_default();
+        let segments = read_vec(r, header[26].into())?;
+        let data = read_vec(r, segments.iter().map(|&s| u64::from(s)).sum())?;
+
+        // pages of other multiplexed streams are skipped
+        if *serial.get_or_insert(page_serial) != page_serial {
+            continue;
+        }
+
+        let mut data = data.as_slice();
+
+        // packets are split into 255 byte segments, with a shorter segment ending the packet
+        for seg in segments {
+            packet.extend_from_slice(take(&mut data, seg.into())?);
+
+            if seg < 255 {
+                packets.push(mem::take(&mut packet));
+
+                if packets.len() == n {
+                    return Ok((page_serial, packets));
+                }
+            }
+        }
+
+        if packet.len() as u64 > MAX_BLOCK {
+            return Err(invalid("ogg packet is too large"));
+        }
+    }
+}
+
+/// Finds the granule position of the last page of the stream `serial`, which is its length in
+/// samples for every codec supported here
+fn last_granule(r: &mut (impl Read + Seek), serial: u32) -> io::Result<Option<u64>> {
+    let len = r.seek(SeekFrom::End(0))?;
+    let start = len.saturating_sub(64 * 1024);
+
+    r.seek(SeekFrom::Start(start))?;
+    let tail = read_vec(r, len - start)?;
+
+    let mut end = tail.len();
+
+    while let Some(pos) = tail[..end].windows(4).rposition(|w| w == b"OggS") {
+        let page = &tail[pos..];
+
+        if u32_le(page, 14) == Some(serial) {
+            // a granule of -1 means no packet ends on this page
+            if let Some(granule) = u64_le(page, 6).filter(|&g| g != u64::MAX) {
+                return Ok(Some(granule));
+            }
+        }
+
+        end = pos;
+    }
+
+    Ok(None)
+}
+
+pub fn ogg(r: &mut (impl Read + Seek), info: &mut TrackInfo) -> io::Result<()> {
+    let (serial, packets) = packets(r, 2)?;
+    let (head, tags) = (&packets[0], &packets[1]);
+
+    let (codec, rate, pre_skip, tags) = if head.starts_with(b"\x01vorbis") {
+        let tags = tags.strip_prefix(b"\x03vorbis");
+        ("vorbis", u32_le(head, 12), 0, tags)
+    } else if head.starts_with(b"OpusHead") {
+        // opus always runs at 48khz, the rate in the header is only informational
+        let tags = tags.strip_prefix(b"OpusTags");
+        (
+            "opus",
+            Some(48_000),
+            u16_le(head, 10).unwrap_or_default(),
+            tags,
+        )
+    } else if head.starts_with(b"Speex   ") {
+        ("speex", u32_le(head, 36), 0, Some(tags.as_slice()))
+    } else if head.starts_with(b"\x7fFLAC") {
+        // the mapping header is followed by `fLaC` and a regular STREAMINFO block
+        let rate = streaminfo(head.get(17..).unwrap_or_default()).map(|(rate, _)| rate);
+        ("flac", rate, 0, tags.get(4..))
+    } else {
+        return Ok(());
+    };
+
+    info.codec = Some(codec.into());
+
+    if let Some(tags) = tags {
+        comments(tags, info)?;
+    }
+
+    if let (Some(rate), Some(granule)) = (rate, last_granule(r, serial)?) {
+        info.duration = samples_duration(granule.saturating_sub(pre_skip.into()), rate);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{super::read_bytes, comments, flac, ogg, TrackInfo};
+
+    /// A vorbis comment block with the given fields
+    fn block(fields: &[&str]) -> Vec<u8> {
+        let len = |n: usize| u32::try_from(n).unwrap().to_le_bytes();
+
+        let mut out = [&len(6)[..], b"vendor", &len(fields.len())].concat();
+
+        for field in fields {
+            out.extend_from_slice(&len(field.len()));
+            out.extend_from_slice(field.as_bytes());
+        }
+
+        out
+    }
+
+    /// A flac STREAMINFO block body for 16 bit stereo
+    fn streaminfo(rate: u64, samples: u64) -> Vec<u8> {
+        let packed = rate << 44 | 1 << 41 | 15 << 36 | samples;
+        [&[0; 10][..], &packed.to_be_bytes(), &[0; 16]].concat()
+    }
+
+    fn metadata_block(kind: u8, body: &[u8]) -> Vec<u8> {
+        let len = u32::try_from(body.len()).unwrap().to_be_bytes();
+        [&[kind, len[1], len[2], len[3]][..], body].concat()
+    }
+
+    #[test]
+    fn reads_comments() {
+        let mut info = TrackInfo::default();
+        comments(
+            &block(&["TITLE=Song", "TRACKNUMBER=3", "ALBUMARTIST=Band", "junk"]),
+            &mut info,
+        )
+        .unwrap();
+
+        assert_eq!(info.tag("title"), Some("Song"));
+        assert_eq!(info.tag("track"), Some("3"));
+        assert_eq!(info.tag("album_artist"), Some("Band"));
+        assert_eq!(info.tags.len(), 3);
+    }
+
+    #[test]
+    fn rejects_truncated_comments() {
+        // claims far more fields than there are, which must not be allocated up front
+        let mut data = block(&["TITLE=Song"]);
+        data[10..14].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        assert!(comments(&data, &mut TrackInfo::default()).is_err());
+
+        let mut data = block(&["TITLE=Song"]);
+        data.truncate(data.len() - 1);
+
+        assert!(comments(&data, &mut TrackInfo::default()).is_err());
+    }
+
+    #[test]
+    fn reads_flac() {
+        let file = [
+            &b"fLaC"[..],
+            &metadata_block(0, &streaminfo(44_100, 441_000)),
+            &metadata_block(1, &[0; 32]),
+            &metadata_block(0x84, &block(&["TITLE=Song"])),
+        ]
+        .concat();
+
+        let info = read_bytes(flac, file).unwrap();
+
+        assert_eq!(info.codec.as_deref(), Some("flac"));
+        assert_eq!(info.tag("title"), Some("Song"));
+        assert_eq!(info.duration.map(|d| d.as_secs()), Some(10));
+    }
+
+    #[test]
+    fn rejects_truncated_flac() {
+        let mut file = [&b"fLaC"[..], &metadata_block(0x84, &block(&["TITLE=Song"]))].concat();
+        file.truncate(file.len() - 4);
+
+        let err = read_bytes(flac, file).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // a stream without a block marked as the last one
+        let file = [&b"fLaC"[..], &metadata_block(1, &[0; 8])].concat();
+        assert!(read_bytes(flac, file).is_err());
+    }
+
+    /// An ogg page of stream 1, holding whole `packets`
+    fn page(granule: u64, packets: &[&[u8]]) -> Vec<u8> {
+        let mut segments = vec![];
+
+        for packet in packets {
+            segments.extend(std::iter::repeat_n(255, packet.len() / 255));
+            segments.push(u8::try_from(packet.len() % 255).unwrap());
+        }
+
+        [
+            &b"OggS\0\0"[..],
+            &granule.to_le_bytes(),
+            &1u32.to_le_bytes(),
+            &[0; 8],
+            &[u8::try_from(segments.len()).unwrap()],
+            &segments,
+            &packets.concat(),
+        ]
+        .concat()
+    }
+
+    fn opus_head(pre_skip: u16) -> Vec<u8> {
+        [
+            &b"OpusHead\x01\x02"[..],
+            &pre_skip.to_le_bytes(),
+            &48_000u32.to_le_bytes(),
+            &[0; 3],
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn reads_opus() {
+        let tags = [
+            &b"OpusTags"[..],
+            &block(&["TITLE=Song", "PADDING=".repeat(40).as_str()]),
+        ]
+        .concat();
+
+        let file = [
+            page(0, &[&opus_head(312)]),
+            page(0, &[&tags]),
+            page(96_312, &[&[0; 20]]),
+        ]
+        .concat();
+
+        let info = read_bytes(ogg, file).unwrap();
+
+        assert_eq!(info.codec.as_deref(), Some("opus"));
+        assert_eq!(info.tag("title"), Some("Song"));
+        assert_eq!(info.duration.map(|d| d.as_secs()), Some(2));
+    }
+
+    #[test]
+    fn rejects_bad_ogg() {
+        let mut file = page(0, &[&opus_head(0)]);
+        file.truncate(file.len() - 4);
+
+        let err = read_bytes(ogg, file).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let file = [page(0, &[&opus_head(0)]), b"OggX".repeat(8)].concat();
+
+        let err = read_bytes(ogg, file).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}